@@ -1,6 +1,6 @@
 #![allow(dead_code)]
 
-use routex::{if_nametoindex, Route, RouteAction, RouteSock};
+use routex::{if_nametoindex, Route, RouteAction, RouteOp, RouteSock};
 
 use std::fs::read_to_string;
 
@@ -43,13 +43,21 @@ fn add_list() {
     let mut handle = RouteSock::new().unwrap();
     let ifindex = if_nametoindex("utun4").unwrap();
 
+    let mut ops = vec![];
     for line in read_to_string(NON_CN_LIST).unwrap().lines() {
         if let Some((ip, cidr)) = line.split_once('/') {
             let route = Route::new(ip.parse().unwrap(), cidr.parse().unwrap()).ifindex(ifindex);
 
-            handle.add(&route).unwrap();
+            ops.push(RouteOp::Add(route));
         } else {
             println!("{} can not be splited by `/`", line);
         }
     }
+
+    let results = handle.batch(&ops).unwrap();
+    for (op, ret) in ops.iter().zip(results) {
+        if let Err(e) = ret {
+            println!("{:?}: {}", op, e);
+        }
+    }
 }
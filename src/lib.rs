@@ -1,23 +1,28 @@
 use std::{
     ffi::CString,
     io,
-    net::IpAddr,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
 };
 
-#[cfg(all(target_os = "macos"))]
+use ipnetwork::IpNetwork;
+
+#[cfg(target_os = "macos")]
 mod macos;
-#[cfg(all(target_os = "linux"))]
+#[cfg(target_os = "linux")]
 mod linux;
+mod transaction;
+
+pub use transaction::{RouteTransaction, TransactionError};
 
-#[cfg(all(target_os = "linux"))]
-pub use linux::RouteSock;
-#[cfg(all(target_os = "macos"))]
+#[cfg(target_os = "linux")]
+pub use linux::{MplsRoute, RouteEvent, RouteLookup, RouteSock};
+#[cfg(target_os = "macos")]
 pub use macos::RouteSock;
 
 #[macro_export]
 macro_rules! syscall {
     ($fn: ident ( $($arg: expr),* ) ) => {{
-        #[allow(unused_unsafe)]
+        #[allow(unused_unsafe, clippy::macro_metavars_in_unsafe)]
         let res = unsafe { libc::$fn($( $arg), *) };
         if res < 0 {
             Err(std::io::Error::last_os_error())
@@ -27,23 +32,77 @@ macro_rules! syscall {
     }};
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Route {
     pub destination: IpAddr,
     pub prefix: u8,
+    /// Next hop address. On Linux an IPv4 route may use an IPv6 gateway,
+    /// which is sent as `RTA_VIA`.
     pub gateway: Option<IpAddr>,
     pub ifindex: Option<u32>,
+    /// Routing table id, `None` means the main table.
+    /// Routes read back from the kernel always carry their table.
+    pub table: Option<u32>,
+    /// Route priority (`RTA_PRIORITY`), lower is preferred.
+    pub metric: Option<u32>,
+    /// Preferred source address (`RTA_PREFSRC`) for traffic using this route.
+    pub pref_source: Option<IpAddr>,
+    /// Source prefix of a source-specific route (`RTA_SRC`, `rtm_src_len`).
+    pub source: Option<(IpAddr, u8)>,
+    pub kind: RouteKind,
+    /// Next hops of a multipath (ECMP) route, empty for a single path route.
+    pub next_hops: Vec<NextHop>,
+    pub metrics: RouteMetrics,
+    /// Routing protocol (`rtm_protocol`) that installed the route,
+    /// `None` adds routes as `RTPROT_BOOT`. Custom ids are allowed.
+    pub protocol: Option<u8>,
+    /// Route scope, `None` picks one from the route kind and next hops when adding.
+    pub scope: Option<RouteScope>,
+    /// IPv6 router preference (`RTA_PREF`).
+    pub preference: Option<RoutePreference>,
+    /// Lifetime in seconds (`RTA_EXPIRES`) of an IPv6 route, the kernel
+    /// removes the route once it runs out. Adding an IPv4 route with a
+    /// lifetime fails with `InvalidInput`. Routes read back carry the
+    /// remaining lifetime.
+    pub expires: Option<u32>,
+    /// Flags of the single next hop, e.g. `NextHopFlags::ONLINK` for a
+    /// gateway outside the interface subnet. Multipath routes use `NextHop::flags`.
+    pub flags: NextHopFlags,
+    /// Lightweight tunnel encapsulation applied to packets using this route.
+    pub encap: Option<RouteEncap>,
+    /// Nexthop object (`RTA_NH_ID`) to forward through, used instead of
+    /// `gateway`, `ifindex` and `next_hops`. See `NexthopAction`.
+    pub nexthop_id: Option<u32>,
+    /// Type of service (`rtm_tos`) of a TOS-specific IPv4 route, the DSCP
+    /// shifted left by two. Routes with a TOS only match packets carrying it.
+    pub tos: Option<u8>,
+    /// Realms (`RTA_FLOW`) as `(from, to)`, used for per-class accounting.
+    pub realm: Option<(u16, u16)>,
 }
 
 impl Default for Route {
     fn default() -> Self {
-        use std::net::Ipv4Addr;
-
         Route {
             destination: IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
             prefix: 0,
             gateway: None,
             ifindex: None,
+            table: None,
+            metric: None,
+            pref_source: None,
+            source: None,
+            kind: RouteKind::Unicast,
+            next_hops: Vec::new(),
+            metrics: RouteMetrics::default(),
+            protocol: None,
+            scope: None,
+            preference: None,
+            expires: None,
+            flags: NextHopFlags::default(),
+            encap: None,
+            nexthop_id: None,
+            tos: None,
+            realm: None,
         }
     }
 }
@@ -53,15 +112,12 @@ impl Route {
         Route {
             destination,
             prefix,
-            gateway: None,
-            ifindex: None,
+            ..Default::default()
         }
     }
 
     #[cfg(target_os = "macos")]
     pub(crate) fn mask(&self) -> IpAddr {
-        use std::net::{Ipv4Addr, Ipv6Addr};
-
         match self.destination {
             IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::from(
                 u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0),
@@ -74,8 +130,6 @@ impl Route {
 
     #[cfg(target_os = "macos")]
     pub(crate) fn cidr(&mut self, netmask: IpAddr) {
-        use std::net::{Ipv4Addr, Ipv6Addr};
-
         self.prefix = match netmask {
             IpAddr::V4(netmask) => <Ipv4Addr as Into<u32>>::into(netmask).leading_ones() as u8,
             IpAddr::V6(netmask) => <Ipv6Addr as Into<u128>>::into(netmask).leading_ones() as u8,
@@ -96,6 +150,533 @@ impl Route {
         self.ifindex = if_nametoindex(interface);
         self
     }
+
+    pub fn table(mut self, table: u32) -> Route {
+        self.table = Some(table);
+        self
+    }
+
+    pub fn metric(mut self, metric: u32) -> Route {
+        self.metric = Some(metric);
+        self
+    }
+
+    pub fn pref_source(mut self, pref_source: IpAddr) -> Route {
+        self.pref_source = Some(pref_source);
+        self
+    }
+
+    pub fn source(mut self, source: IpAddr, prefix: u8) -> Route {
+        self.source = Some((source, prefix));
+        self
+    }
+
+    pub fn kind(mut self, kind: RouteKind) -> Route {
+        self.kind = kind;
+        self
+    }
+
+    pub fn next_hop(mut self, next_hop: NextHop) -> Route {
+        self.next_hops.push(next_hop);
+        self
+    }
+
+    pub fn metrics(mut self, metrics: RouteMetrics) -> Route {
+        self.metrics = metrics;
+        self
+    }
+
+    pub fn protocol(mut self, protocol: u8) -> Route {
+        self.protocol = Some(protocol);
+        self
+    }
+
+    pub fn scope(mut self, scope: RouteScope) -> Route {
+        self.scope = Some(scope);
+        self
+    }
+
+    pub fn preference(mut self, preference: RoutePreference) -> Route {
+        self.preference = Some(preference);
+        self
+    }
+
+    pub fn expires(mut self, seconds: u32) -> Route {
+        self.expires = Some(seconds);
+        self
+    }
+
+    pub fn flags(mut self, flags: NextHopFlags) -> Route {
+        self.flags = flags;
+        self
+    }
+
+    pub fn onlink(mut self) -> Route {
+        self.flags |= NextHopFlags::ONLINK;
+        self
+    }
+
+    pub fn encap(mut self, encap: RouteEncap) -> Route {
+        self.encap = Some(encap);
+        self
+    }
+
+    pub fn nexthop_id(mut self, id: u32) -> Route {
+        self.nexthop_id = Some(id);
+        self
+    }
+
+    pub fn tos(mut self, tos: u8) -> Route {
+        self.tos = Some(tos);
+        self
+    }
+
+    pub fn realm(mut self, from: u16, to: u16) -> Route {
+        self.realm = Some((from, to));
+        self
+    }
+}
+
+/// Lightweight tunnel encapsulation (`RTA_ENCAP_TYPE`, `RTA_ENCAP`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RouteEncap {
+    /// Push an MPLS label stack, outermost label first.
+    Mpls {
+        labels: Vec<u32>,
+        /// TTL of the pushed labels, `None` copies it from the IP header
+        ttl: Option<u8>,
+    },
+    /// Steer packets through a list of SRv6 segments, first segment first.
+    Seg6 {
+        mode: Seg6Mode,
+        segments: Vec<Ipv6Addr>,
+    },
+    /// Process packets addressed to a local SRv6 segment.
+    Seg6Local(Seg6LocalAction),
+    /// Tunnel metadata for collect-metadata (flow based) tunnel devices,
+    /// e.g. VXLAN or Geneve in external mode. The outer family follows `dst`.
+    Ip(IpTunnelEncap),
+}
+
+/// `encap ip` / `encap ip6` parameters.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IpTunnelEncap {
+    /// Tunnel key, e.g. the VNI
+    pub id: Option<u64>,
+    /// Remote tunnel endpoint
+    pub dst: Option<IpAddr>,
+    /// Local tunnel endpoint
+    pub src: Option<IpAddr>,
+    /// TOS, or traffic class for IPv6
+    pub tos: Option<u8>,
+    /// TTL, or hop limit for IPv6
+    pub ttl: Option<u8>,
+    /// `IpTunnelEncap::FLAG_*`
+    pub flags: u16,
+}
+
+impl IpTunnelEncap {
+    pub const FLAG_CSUM: u16 = 0x01;
+    pub const FLAG_KEY: u16 = 0x04;
+    pub const FLAG_SEQ: u16 = 0x08;
+}
+
+/// How `RouteEncap::Seg6` adds the segment routing header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Seg6Mode {
+    /// Insert the SRH into the original IPv6 packet
+    Inline,
+    /// Wrap the packet in an outer IPv6 header carrying the SRH
+    Encap,
+}
+
+/// SRv6 local segment behavior of RFC 8986.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Seg6LocalAction {
+    /// Advance to the next segment
+    End,
+    /// Decapsulate and forward the inner IPv4 packet to `nh4`
+    EndDx4 { nh4: Ipv4Addr },
+    /// Decapsulate and look the inner IPv6 packet up in `table`
+    EndDt6 { table: u32 },
+}
+
+/// Per-route metrics (`RTA_METRICS`), unset ones are left to the kernel.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RouteMetrics {
+    pub mtu: Option<u32>,
+    pub advmss: Option<u32>,
+    pub window: Option<u32>,
+    pub rtt: Option<u32>,
+    pub initcwnd: Option<u32>,
+    pub initrwnd: Option<u32>,
+    pub hoplimit: Option<u32>,
+    pub quickack: Option<bool>,
+    /// Congestion control algorithm name, e.g. `bbr`
+    pub congctl: Option<String>,
+    /// Metrics the kernel must not update on its own, see `RouteMetrics::LOCK_*`
+    pub lock: u32,
+}
+
+impl RouteMetrics {
+    pub const LOCK_MTU: u32 = 1 << 2;
+    pub const LOCK_WINDOW: u32 = 1 << 3;
+    pub const LOCK_RTT: u32 = 1 << 4;
+    pub const LOCK_ADVMSS: u32 = 1 << 8;
+    pub const LOCK_HOPLIMIT: u32 = 1 << 10;
+    pub const LOCK_INITCWND: u32 = 1 << 11;
+    pub const LOCK_INITRWND: u32 = 1 << 14;
+    pub const LOCK_CONGCTL: u32 = 1 << 16;
+
+    pub fn is_empty(&self) -> bool {
+        *self == RouteMetrics::default()
+    }
+}
+
+/// One path of a multipath route (`struct rtnexthop`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NextHop {
+    pub gateway: Option<IpAddr>,
+    pub ifindex: Option<u32>,
+    /// Relative weight of this path, from 1 to 256.
+    pub weight: u16,
+    pub flags: NextHopFlags,
+}
+
+impl Default for NextHop {
+    fn default() -> Self {
+        NextHop {
+            gateway: None,
+            ifindex: None,
+            weight: 1,
+            flags: NextHopFlags::default(),
+        }
+    }
+}
+
+impl NextHop {
+    pub fn new() -> NextHop {
+        NextHop::default()
+    }
+
+    pub fn gateway(mut self, gateway: IpAddr) -> NextHop {
+        self.gateway = Some(gateway);
+        self
+    }
+
+    pub fn ifindex(mut self, ifindex: u32) -> NextHop {
+        self.ifindex = Some(ifindex);
+        self
+    }
+
+    pub fn interface(mut self, interface: &str) -> NextHop {
+        self.ifindex = if_nametoindex(interface);
+        self
+    }
+
+    pub fn weight(mut self, weight: u16) -> NextHop {
+        self.weight = weight;
+        self
+    }
+
+    pub fn flags(mut self, flags: NextHopFlags) -> NextHop {
+        self.flags = flags;
+        self
+    }
+}
+
+/// Next hop flags (`RTNH_F_*`). `ONLINK` and `PERVASIVE` can be set when
+/// adding, the others are reported by the kernel.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NextHopFlags(pub u8);
+
+impl NextHopFlags {
+    pub const DEAD: NextHopFlags = NextHopFlags(1);
+    pub const PERVASIVE: NextHopFlags = NextHopFlags(2);
+    pub const ONLINK: NextHopFlags = NextHopFlags(4);
+    pub const OFFLOAD: NextHopFlags = NextHopFlags(8);
+    pub const LINKDOWN: NextHopFlags = NextHopFlags(16);
+    pub const UNRESOLVED: NextHopFlags = NextHopFlags(32);
+    pub const TRAP: NextHopFlags = NextHopFlags(64);
+
+    pub fn contains(self, other: NextHopFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl std::ops::BitOr for NextHopFlags {
+    type Output = NextHopFlags;
+
+    fn bitor(self, rhs: NextHopFlags) -> NextHopFlags {
+        NextHopFlags(self.0 | rhs.0)
+    }
+}
+
+impl std::ops::BitOrAssign for NextHopFlags {
+    fn bitor_assign(&mut self, rhs: NextHopFlags) {
+        self.0 |= rhs.0
+    }
+}
+
+/// A nexthop object (`RTM_NEWNEXTHOP`) shared by routes through
+/// `Route::nexthop_id`. Replacing it updates all of them at once.
+///
+/// A nexthop either forwards through `gateway`/`ifindex`, drops packets
+/// (`blackhole`) or is a group of other nexthops (`group`).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NexthopObject {
+    /// Nexthop id, `0` lets the kernel allocate one when adding.
+    pub id: u32,
+    pub gateway: Option<IpAddr>,
+    pub ifindex: Option<u32>,
+    pub flags: NextHopFlags,
+    pub blackhole: bool,
+    /// Address family of a nexthop without gateway, IPv4 unless set.
+    /// Nexthops with a gateway take its family, groups have none.
+    pub ipv6: bool,
+    /// Members of a nexthop group, empty for a single nexthop.
+    pub group: Vec<NexthopGroupMember>,
+    /// Hash buckets settings of a resilient group, `None` for a plain
+    /// multipath group.
+    pub resilient: Option<ResilientGroup>,
+    /// Routing protocol (`nh_protocol`) that installed the nexthop,
+    /// `None` adds nexthops as `RTPROT_BOOT`.
+    pub protocol: Option<u8>,
+}
+
+impl NexthopObject {
+    pub fn new(id: u32) -> NexthopObject {
+        NexthopObject {
+            id,
+            ..Default::default()
+        }
+    }
+
+    pub fn gateway(mut self, gateway: IpAddr) -> NexthopObject {
+        self.gateway = Some(gateway);
+        self
+    }
+
+    pub fn ifindex(mut self, ifindex: u32) -> NexthopObject {
+        self.ifindex = Some(ifindex);
+        self
+    }
+
+    pub fn interface(mut self, interface: &str) -> NexthopObject {
+        self.ifindex = if_nametoindex(interface);
+        self
+    }
+
+    pub fn flags(mut self, flags: NextHopFlags) -> NexthopObject {
+        self.flags = flags;
+        self
+    }
+
+    pub fn onlink(mut self) -> NexthopObject {
+        self.flags |= NextHopFlags::ONLINK;
+        self
+    }
+
+    pub fn blackhole(mut self) -> NexthopObject {
+        self.blackhole = true;
+        self
+    }
+
+    pub fn ipv6(mut self) -> NexthopObject {
+        self.ipv6 = true;
+        self
+    }
+
+    /// Adds nexthop `id` with `weight` (1 to 256) to the group
+    pub fn member(mut self, id: u32, weight: u16) -> NexthopObject {
+        self.group.push(NexthopGroupMember { id, weight });
+        self
+    }
+
+    pub fn resilient(mut self, resilient: ResilientGroup) -> NexthopObject {
+        self.resilient = Some(resilient);
+        self
+    }
+
+    pub fn protocol(mut self, protocol: u8) -> NexthopObject {
+        self.protocol = Some(protocol);
+        self
+    }
+}
+
+/// One member of a nexthop group (`struct nexthop_grp`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NexthopGroupMember {
+    pub id: u32,
+    /// Relative weight of this member, from 1 to 256.
+    pub weight: u16,
+}
+
+/// Resilient nexthop group settings (`NHA_RES_GROUP`). Unset fields
+/// keep the kernel defaults.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResilientGroup {
+    /// Number of hash buckets spread over the members.
+    pub buckets: Option<u16>,
+    /// Seconds a bucket must be idle before it can move to another member.
+    pub idle_timer: Option<u32>,
+    /// Seconds the group may stay unbalanced before busy buckets are moved too.
+    pub unbalanced_timer: Option<u32>,
+}
+
+/// Route type (`rtm_type`), decides what happens to matching packets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteKind {
+    /// Gateway or direct route
+    Unicast,
+    /// Accept locally
+    Local,
+    /// Accept locally as broadcast, send as broadcast
+    Broadcast,
+    /// Accept locally as broadcast, but send as unicast
+    Anycast,
+    /// Multicast route
+    Multicast,
+    /// Drop silently
+    Blackhole,
+    /// Destination is unreachable, ICMP host unreachable
+    Unreachable,
+    /// Administratively prohibited, ICMP communication prohibited
+    Prohibit,
+    /// Not in this table, continue the policy rule lookup
+    Throw,
+    /// Translate this address
+    Nat,
+    Other(u8),
+}
+
+impl From<u8> for RouteKind {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => RouteKind::Unicast,
+            2 => RouteKind::Local,
+            3 => RouteKind::Broadcast,
+            4 => RouteKind::Anycast,
+            5 => RouteKind::Multicast,
+            6 => RouteKind::Blackhole,
+            7 => RouteKind::Unreachable,
+            8 => RouteKind::Prohibit,
+            9 => RouteKind::Throw,
+            10 => RouteKind::Nat,
+            _ => RouteKind::Other(value),
+        }
+    }
+}
+
+impl From<RouteKind> for u8 {
+    fn from(value: RouteKind) -> Self {
+        match value {
+            RouteKind::Unicast => 1,
+            RouteKind::Local => 2,
+            RouteKind::Broadcast => 3,
+            RouteKind::Anycast => 4,
+            RouteKind::Multicast => 5,
+            RouteKind::Blackhole => 6,
+            RouteKind::Unreachable => 7,
+            RouteKind::Prohibit => 8,
+            RouteKind::Throw => 9,
+            RouteKind::Nat => 10,
+            RouteKind::Other(value) => value,
+        }
+    }
+}
+
+/// Distance to the destination (`rtm_scope`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteScope {
+    /// Everywhere, routes via a gateway
+    Universe,
+    /// Interior routes in the local autonomous system
+    Site,
+    /// Destinations on the attached link
+    Link,
+    /// Addresses of this host
+    Host,
+    /// No destination, used when deleting
+    NoWhere,
+    Other(u8),
+}
+
+impl From<u8> for RouteScope {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => RouteScope::Universe,
+            200 => RouteScope::Site,
+            253 => RouteScope::Link,
+            254 => RouteScope::Host,
+            255 => RouteScope::NoWhere,
+            _ => RouteScope::Other(value),
+        }
+    }
+}
+
+impl From<RouteScope> for u8 {
+    fn from(value: RouteScope) -> Self {
+        match value {
+            RouteScope::Universe => 0,
+            RouteScope::Site => 200,
+            RouteScope::Link => 253,
+            RouteScope::Host => 254,
+            RouteScope::NoWhere => 255,
+            RouteScope::Other(value) => value,
+        }
+    }
+}
+
+/// Default router preference of RFC 4191.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoutePreference {
+    Low,
+    Medium,
+    High,
+    Other(u8),
+}
+
+impl From<u8> for RoutePreference {
+    fn from(value: u8) -> Self {
+        match value {
+            3 => RoutePreference::Low,
+            0 => RoutePreference::Medium,
+            1 => RoutePreference::High,
+            _ => RoutePreference::Other(value),
+        }
+    }
+}
+
+impl From<RoutePreference> for u8 {
+    fn from(value: RoutePreference) -> Self {
+        match value {
+            RoutePreference::Low => 3,
+            RoutePreference::Medium => 0,
+            RoutePreference::High => 1,
+            RoutePreference::Other(value) => value,
+        }
+    }
+}
+
+/// IP version of a route.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpFamily {
+    V4,
+    V6,
+}
+
+impl IpFamily {
+    pub fn of(addr: &IpAddr) -> IpFamily {
+        match addr {
+            IpAddr::V4(_) => IpFamily::V4,
+            IpAddr::V6(_) => IpFamily::V6,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -117,11 +698,212 @@ impl From<u8> for RouteChange {
     }
 }
 
+/// Selects routes by their properties, unset fields match everything.
+///
+/// The macOS routing socket reports no table, protocol or scope, so
+/// `list` there fails with `Unsupported` when any of them is set and
+/// `monitor_filtered` never matches.
+#[derive(Debug, Clone, Default)]
+pub struct RouteFilter {
+    pub family: Option<IpFamily>,
+    /// Routing table id, `None` matches routes of all tables.
+    pub table: Option<u32>,
+    /// Output interface of the route or of any of its next hops.
+    pub ifindex: Option<u32>,
+    pub protocol: Option<u8>,
+    pub kind: Option<RouteKind>,
+    pub scope: Option<RouteScope>,
+    /// Only routes inside this prefix, e.g. `10.0.0.0/8` matches
+    /// `10.1.0.0/16` but neither `0.0.0.0/0` nor `11.0.0.0/8`.
+    pub within: Option<(IpAddr, u8)>,
+}
+
+impl RouteFilter {
+    pub fn new() -> RouteFilter {
+        RouteFilter::default()
+    }
+
+    pub fn family(mut self, family: IpFamily) -> RouteFilter {
+        self.family = Some(family);
+        self
+    }
+
+    pub fn table(mut self, table: u32) -> RouteFilter {
+        self.table = Some(table);
+        self
+    }
+
+    pub fn ifindex(mut self, ifindex: u32) -> RouteFilter {
+        self.ifindex = Some(ifindex);
+        self
+    }
+
+    pub fn interface(mut self, interface: &str) -> RouteFilter {
+        self.ifindex = if_nametoindex(interface);
+        self
+    }
+
+    pub fn protocol(mut self, protocol: u8) -> RouteFilter {
+        self.protocol = Some(protocol);
+        self
+    }
+
+    pub fn kind(mut self, kind: RouteKind) -> RouteFilter {
+        self.kind = Some(kind);
+        self
+    }
+
+    pub fn scope(mut self, scope: RouteScope) -> RouteFilter {
+        self.scope = Some(scope);
+        self
+    }
+
+    pub fn within(mut self, destination: IpAddr, prefix: u8) -> RouteFilter {
+        self.within = Some((destination, prefix));
+        self
+    }
+
+    pub fn matches(&self, route: &Route) -> bool {
+        if self
+            .family
+            .is_some_and(|family| family != IpFamily::of(&route.destination))
+        {
+            return false;
+        }
+
+        if self.table.is_some() && self.table != route.table {
+            return false;
+        }
+
+        if let Some(ifindex) = self.ifindex {
+            let uses = route.ifindex == Some(ifindex)
+                || route.next_hops.iter().any(|nh| nh.ifindex == Some(ifindex));
+            if !uses {
+                return false;
+            }
+        }
+
+        if self.protocol.is_some() && self.protocol != route.protocol {
+            return false;
+        }
+
+        if self.kind.is_some() && self.kind != Some(route.kind) {
+            return false;
+        }
+
+        if self.scope.is_some() && self.scope != route.scope {
+            return false;
+        }
+
+        if let Some((destination, prefix)) = self.within {
+            let inside = route.prefix >= prefix
+                && IpNetwork::new(destination, prefix)
+                    .map(|net| net.contains(route.destination))
+                    .unwrap_or(false);
+            if !inside {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// Outcome of `RouteAction::flush`.
+#[derive(Debug, Default)]
+pub struct FlushReport {
+    /// Number of routes deleted.
+    pub removed: usize,
+    /// Routes which could not be deleted, with the reason.
+    pub failed: Vec<(Route, io::Error)>,
+}
+
+/// A route change applied by `RouteAction::batch`.
+#[derive(Debug, Clone)]
+pub enum RouteOp {
+    Add(Route),
+    Replace(Route),
+    Delete(Route),
+}
+
 pub trait RouteAction {
     fn add(&mut self, route: &Route) -> io::Result<()>;
+    /// Adds `route`, or atomically replaces the route with the same
+    /// destination, table, tos and metric
+    fn replace(&mut self, route: &Route) -> io::Result<()>;
+    /// Like `replace`, but fails with `NotFound` if there is no such route
+    fn change(&mut self, route: &Route) -> io::Result<()>;
+    /// Adds the next hops of `route` to an existing route with the same
+    /// destination and metric (`ip route append`). IPv6 merges them into
+    /// one multipath route, IPv4 keeps the new path as a separate backup.
+    fn append(&mut self, route: &Route) -> io::Result<()>;
+    /// Deletes the route matching the fields set in `route`, like its
+    /// gateway, interface and metric, not just any route to its prefix
     fn delete(&mut self, route: &Route) -> io::Result<()>;
+    /// The most specific route covering `route.destination` with a prefix
+    /// no longer than `route.prefix`, a default `Route` if there is none.
+    ///
+    /// On Linux this matches the routes of `route.table` (main unless set)
+    /// in userland, it is not a kernel lookup: policy rules, marks and
+    /// interface state are ignored. `RouteSock::lookup` asks the kernel
+    /// which route it would actually use.
     fn get(&mut self, route: &Route) -> io::Result<Route>;
+    /// Every route matching `filter`, across address families and tables
+    /// unless the filter narrows them down
+    fn list(&mut self, filter: &RouteFilter) -> io::Result<Vec<Route>>;
     fn monitor(&mut self, buf: &mut [u8]) -> io::Result<(RouteChange, Route)>;
+
+    /// Deletes every route matching `filter`. A failed deletion does not
+    /// stop the others, it is reported along with its route.
+    fn flush(&mut self, filter: &RouteFilter) -> io::Result<FlushReport> {
+        let mut report = FlushReport::default();
+        for route in self.list(filter)? {
+            match self.delete(&route) {
+                Ok(()) => report.removed += 1,
+                Err(e) => report.failed.push((route, e)),
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Applies `ops` in order and returns the result of each of them. One
+    /// failing does not stop the others. Linux sends them in batches
+    /// instead of waiting for every reply.
+    fn batch(&mut self, ops: &[RouteOp]) -> io::Result<Vec<io::Result<()>>> {
+        Ok(ops
+            .iter()
+            .map(|op| match op {
+                RouteOp::Add(route) => self.add(route),
+                RouteOp::Replace(route) => self.replace(route),
+                RouteOp::Delete(route) => self.delete(route),
+            })
+            .collect())
+    }
+
+    /// Like `monitor`, but skips changes of routes not matching `filter`.
+    /// On macOS a filter on table, protocol or scope matches no change.
+    fn monitor_filtered(
+        &mut self,
+        buf: &mut [u8],
+        filter: &RouteFilter,
+    ) -> io::Result<(RouteChange, Route)> {
+        loop {
+            let (change, route) = self.monitor(buf)?;
+            if filter.matches(&route) {
+                return Ok((change, route));
+            }
+        }
+    }
+}
+
+/// Manages nexthop objects, which routes reference by id.
+pub trait NexthopAction {
+    fn add_nexthop(&mut self, nexthop: &NexthopObject) -> io::Result<()>;
+    /// Adds `nexthop`, or updates the nexthop with the same id in place
+    fn replace_nexthop(&mut self, nexthop: &NexthopObject) -> io::Result<()>;
+    fn delete_nexthop(&mut self, id: u32) -> io::Result<()>;
+    fn list_nexthops(&mut self) -> io::Result<Vec<NexthopObject>>;
 }
 
 pub fn if_nametoindex(name: &str) -> Option<u32> {
@@ -130,3 +912,67 @@ pub fn if_nametoindex(name: &str) -> Option<u32> {
 
     Some(ifindex)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(destination: &str, prefix: u8) -> Route {
+        Route::new(destination.parse().unwrap(), prefix)
+    }
+
+    #[test]
+    fn filter_within() {
+        let filter = RouteFilter::new().within("10.0.0.0".parse().unwrap(), 8);
+
+        assert!(filter.matches(&route("10.0.0.0", 8)));
+        assert!(filter.matches(&route("10.1.0.0", 16)));
+        assert!(filter.matches(&route("10.255.255.255", 32)));
+        assert!(!filter.matches(&route("0.0.0.0", 0)));
+        assert!(!filter.matches(&route("10.0.0.0", 7)));
+        assert!(!filter.matches(&route("11.0.0.0", 8)));
+        assert!(!filter.matches(&route("::", 0)));
+    }
+
+    #[test]
+    fn filter_family() {
+        let filter = RouteFilter::new().family(IpFamily::V6);
+
+        assert!(filter.matches(&route("2001:db8::", 32)));
+        assert!(!filter.matches(&route("10.0.0.0", 8)));
+    }
+
+    #[test]
+    fn filter_ifindex_matches_next_hops() {
+        let filter = RouteFilter::new().ifindex(2);
+
+        assert!(filter.matches(&route("10.0.0.0", 8).ifindex(2)));
+        assert!(filter.matches(
+            &route("10.0.0.0", 8)
+                .next_hop(NextHop::new().ifindex(3))
+                .next_hop(NextHop::new().ifindex(2))
+        ));
+        assert!(!filter.matches(&route("10.0.0.0", 8).ifindex(3)));
+        assert!(!filter.matches(&route("10.0.0.0", 8)));
+    }
+
+    #[test]
+    fn filter_attributes() {
+        let r = route("10.0.0.0", 8)
+            .table(100)
+            .protocol(4)
+            .kind(RouteKind::Blackhole)
+            .scope(RouteScope::Universe);
+
+        assert!(RouteFilter::new().matches(&r));
+        assert!(RouteFilter::new().table(100).matches(&r));
+        assert!(!RouteFilter::new().table(254).matches(&r));
+        assert!(!RouteFilter::new().table(100).matches(&route("10.0.0.0", 8)));
+        assert!(RouteFilter::new().protocol(4).matches(&r));
+        assert!(!RouteFilter::new().protocol(3).matches(&r));
+        assert!(RouteFilter::new().kind(RouteKind::Blackhole).matches(&r));
+        assert!(!RouteFilter::new().kind(RouteKind::Unicast).matches(&r));
+        assert!(RouteFilter::new().scope(RouteScope::Universe).matches(&r));
+        assert!(!RouteFilter::new().scope(RouteScope::Link).matches(&r));
+    }
+}
@@ -0,0 +1,187 @@
+use std::{io, net::IpAddr};
+
+use netlink_packet_core::NLM_F_REQUEST;
+use netlink_packet_route::{
+    route::{RouteAttribute, RouteFlag, RouteMessage},
+    RouteNetlinkMessage,
+};
+use netlink_packet_utils::nla::DefaultNla;
+
+use super::{
+    rtmsg::{family, parse_route, route_address},
+    RouteSock,
+};
+use crate::{if_nametoindex, Route};
+
+const RTA_IP_PROTO: u16 = 27;
+const RTA_SPORT: u16 = 28;
+const RTA_DPORT: u16 = 29;
+
+/// A kernel route lookup (`ip route get`), honouring policy rules,
+/// all tables, metrics and multipath hashing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteLookup {
+    pub destination: IpAddr,
+    pub source: Option<IpAddr>,
+    /// Input interface, looks the packet up as if it was received there
+    /// instead of sent locally. Needs `source`.
+    pub iif: Option<u32>,
+    /// Output interface the lookup is restricted to.
+    pub oif: Option<u32>,
+    /// Firewall mark (`fwmark`) matched by policy rules.
+    pub mark: Option<u32>,
+    /// Socket owner matched by `uidrange` policy rules.
+    pub uid: Option<u32>,
+    /// IP protocol, e.g. `libc::IPPROTO_TCP`, used by policy rules and
+    /// multipath hashing together with the ports.
+    pub ip_proto: Option<u8>,
+    pub sport: Option<u16>,
+    pub dport: Option<u16>,
+    /// Return the matching FIB entry, with all its next hops, instead of
+    /// the route resolved for this destination.
+    pub fibmatch: bool,
+}
+
+impl RouteLookup {
+    pub fn new(destination: IpAddr) -> RouteLookup {
+        RouteLookup {
+            destination,
+            source: None,
+            iif: None,
+            oif: None,
+            mark: None,
+            uid: None,
+            ip_proto: None,
+            sport: None,
+            dport: None,
+            fibmatch: false,
+        }
+    }
+
+    pub fn source(mut self, source: IpAddr) -> RouteLookup {
+        self.source = Some(source);
+        self
+    }
+
+    pub fn iif(mut self, ifindex: u32) -> RouteLookup {
+        self.iif = Some(ifindex);
+        self
+    }
+
+    pub fn input_interface(mut self, interface: &str) -> RouteLookup {
+        self.iif = if_nametoindex(interface);
+        self
+    }
+
+    pub fn oif(mut self, ifindex: u32) -> RouteLookup {
+        self.oif = Some(ifindex);
+        self
+    }
+
+    pub fn output_interface(mut self, interface: &str) -> RouteLookup {
+        self.oif = if_nametoindex(interface);
+        self
+    }
+
+    pub fn mark(mut self, mark: u32) -> RouteLookup {
+        self.mark = Some(mark);
+        self
+    }
+
+    pub fn uid(mut self, uid: u32) -> RouteLookup {
+        self.uid = Some(uid);
+        self
+    }
+
+    pub fn ip_proto(mut self, ip_proto: u8) -> RouteLookup {
+        self.ip_proto = Some(ip_proto);
+        self
+    }
+
+    pub fn sport(mut self, port: u16) -> RouteLookup {
+        self.sport = Some(port);
+        self
+    }
+
+    pub fn dport(mut self, port: u16) -> RouteLookup {
+        self.dport = Some(port);
+        self
+    }
+
+    pub fn fibmatch(mut self) -> RouteLookup {
+        self.fibmatch = true;
+        self
+    }
+}
+
+fn host_prefix(addr: &IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+fn lookup_message(query: &RouteLookup) -> RouteMessage {
+    let mut rt_msg = RouteMessage::default();
+    rt_msg.header.address_family = family(&query.destination);
+    rt_msg.header.destination_prefix_length = host_prefix(&query.destination);
+    // report the table the route was found in
+    rt_msg.header.flags.push(RouteFlag::LookupTable);
+    if query.fibmatch {
+        rt_msg.header.flags.push(RouteFlag::FibMatch);
+    }
+
+    rt_msg
+        .attributes
+        .push(RouteAttribute::Destination(route_address(
+            &query.destination,
+        )));
+    if let Some(source) = query.source {
+        rt_msg.header.source_prefix_length = host_prefix(&source);
+        rt_msg
+            .attributes
+            .push(RouteAttribute::Source(route_address(&source)));
+    }
+    if let Some(iif) = query.iif {
+        rt_msg.attributes.push(RouteAttribute::Iif(iif));
+    }
+    if let Some(oif) = query.oif {
+        rt_msg.attributes.push(RouteAttribute::Oif(oif));
+    }
+    if let Some(mark) = query.mark {
+        rt_msg.attributes.push(RouteAttribute::Mark(mark));
+    }
+    if let Some(uid) = query.uid {
+        rt_msg.attributes.push(RouteAttribute::Uid(uid));
+    }
+
+    let nla = |kind, value| RouteAttribute::Other(DefaultNla::new(kind, value));
+    if let Some(ip_proto) = query.ip_proto {
+        rt_msg.attributes.push(nla(RTA_IP_PROTO, vec![ip_proto]));
+    }
+    if let Some(port) = query.sport {
+        rt_msg
+            .attributes
+            .push(nla(RTA_SPORT, port.to_be_bytes().to_vec()));
+    }
+    if let Some(port) = query.dport {
+        rt_msg
+            .attributes
+            .push(nla(RTA_DPORT, port.to_be_bytes().to_vec()));
+    }
+
+    rt_msg
+}
+
+impl RouteSock {
+    /// Asks the kernel which route `query` takes. Fails with
+    /// `NetworkUnreachable` if no route matches.
+    pub fn lookup(&mut self, query: &RouteLookup) -> io::Result<Route> {
+        self.request(
+            RouteNetlinkMessage::GetRoute(lookup_message(query)),
+            NLM_F_REQUEST,
+        )?;
+
+        Ok(parse_route(&self.recv_route()?))
+    }
+}
@@ -0,0 +1,399 @@
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+use netlink_packet_route::route::{
+    RouteAttribute, RouteLwEnCapType, RouteLwTunnelEncap, RouteMessage, RouteMplsIpTunnel,
+};
+use netlink_packet_utils::nla::{DefaultNla, Nla};
+
+use super::rtmsg::mpls_labels;
+use crate::{IpTunnelEncap, RouteEncap, Seg6LocalAction, Seg6Mode};
+
+const SEG6_IPTUNNEL_SRH: u16 = 1;
+const SEG6_IPTUN_MODE_INLINE: u32 = 0;
+const SEG6_IPTUN_MODE_ENCAP: u32 = 1;
+const IPV6_SRCRT_TYPE_4: u8 = 4;
+
+const SEG6_LOCAL_ACTION: u16 = 1;
+const SEG6_LOCAL_TABLE: u16 = 3;
+const SEG6_LOCAL_NH4: u16 = 4;
+
+const SEG6_LOCAL_ACTION_END: u32 = 1;
+const SEG6_LOCAL_ACTION_END_DX4: u32 = 6;
+const SEG6_LOCAL_ACTION_END_DT6: u32 = 7;
+
+// LWTUNNEL_IP_* and LWTUNNEL_IP6_* share their ids
+const LWTUNNEL_IP_ID: u16 = 1;
+const LWTUNNEL_IP_DST: u16 = 2;
+const LWTUNNEL_IP_SRC: u16 = 3;
+const LWTUNNEL_IP_TTL: u16 = 4;
+const LWTUNNEL_IP_TOS: u16 = 5;
+const LWTUNNEL_IP_FLAGS: u16 = 6;
+
+pub(super) fn nla_value(nla: &DefaultNla) -> Vec<u8> {
+    let mut value = vec![0u8; nla.value_len()];
+    nla.emit_value(&mut value);
+    value
+}
+
+/// `struct seg6_iptunnel_encap`: the mode followed by an SRH
+/// holding the segments in reverse order, the way iproute2 builds it.
+fn seg6_iptunnel(mode: Seg6Mode, segments: &[Ipv6Addr]) -> io::Result<Vec<u8>> {
+    if segments.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "seg6 encapsulation needs at least one segment",
+        ));
+    }
+
+    let mut segments: Vec<Ipv6Addr> = segments.iter().rev().copied().collect();
+    let mode = match mode {
+        Seg6Mode::Inline => {
+            // segment 0 is the final destination, filled in by the kernel
+            segments.insert(0, Ipv6Addr::UNSPECIFIED);
+            SEG6_IPTUN_MODE_INLINE
+        }
+        Seg6Mode::Encap => SEG6_IPTUN_MODE_ENCAP,
+    };
+    // hdrlen counts 8 byte units, two per segment, in a u8
+    if segments.len() > 127 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "seg6 encapsulation holds at most 127 segments, 126 inline",
+        ));
+    }
+    let last = segments.len().saturating_sub(1) as u8;
+    let srh_len = 8 + 16 * segments.len();
+
+    let mut buf = Vec::with_capacity(4 + srh_len);
+    buf.extend_from_slice(&mode.to_ne_bytes());
+    buf.extend_from_slice(&[
+        0,                       // nexthdr
+        (srh_len / 8 - 1) as u8, // hdrlen
+        IPV6_SRCRT_TYPE_4,       // type
+        last,                    // segments_left
+        last,                    // first_segment
+        0,                       // flags
+        0,                       // tag
+        0,
+    ]);
+    for segment in segments {
+        buf.extend_from_slice(&segment.octets());
+    }
+
+    Ok(buf)
+}
+
+fn parse_seg6_iptunnel(buf: &[u8]) -> Option<RouteEncap> {
+    if buf.len() < 12 {
+        return None;
+    }
+
+    let mode = match u32::from_ne_bytes(buf[..4].try_into().unwrap()) {
+        SEG6_IPTUN_MODE_INLINE => Seg6Mode::Inline,
+        SEG6_IPTUN_MODE_ENCAP => Seg6Mode::Encap,
+        _ => return None,
+    };
+    let srh = &buf[4..];
+    let first = srh[4] as usize;
+    let skip = if mode == Seg6Mode::Inline { 1 } else { 0 };
+
+    let segments = (skip..=first)
+        .rev()
+        .filter_map(|i| srh.get(8 + 16 * i..8 + 16 * (i + 1)))
+        .map(|segment| Ipv6Addr::from(<[u8; 16]>::try_from(segment).unwrap()))
+        .collect();
+
+    Some(RouteEncap::Seg6 { mode, segments })
+}
+
+fn seg6_local(action: &Seg6LocalAction) -> Vec<RouteLwTunnelEncap> {
+    let nla = |kind, value| RouteLwTunnelEncap::Other(DefaultNla::new(kind, value));
+
+    match action {
+        Seg6LocalAction::End => vec![nla(
+            SEG6_LOCAL_ACTION,
+            SEG6_LOCAL_ACTION_END.to_ne_bytes().to_vec(),
+        )],
+        Seg6LocalAction::EndDx4 { nh4 } => vec![
+            nla(
+                SEG6_LOCAL_ACTION,
+                SEG6_LOCAL_ACTION_END_DX4.to_ne_bytes().to_vec(),
+            ),
+            nla(SEG6_LOCAL_NH4, nh4.octets().to_vec()),
+        ],
+        Seg6LocalAction::EndDt6 { table } => vec![
+            nla(
+                SEG6_LOCAL_ACTION,
+                SEG6_LOCAL_ACTION_END_DT6.to_ne_bytes().to_vec(),
+            ),
+            nla(SEG6_LOCAL_TABLE, table.to_ne_bytes().to_vec()),
+        ],
+    }
+}
+
+fn parse_seg6_local(nlas: &[RouteLwTunnelEncap]) -> Option<RouteEncap> {
+    let mut action = None;
+    let mut table = None;
+    let mut nh4 = None;
+
+    for nla in nlas {
+        if let RouteLwTunnelEncap::Other(nla) = nla {
+            let value = nla_value(nla);
+            match (nla.kind(), value.len()) {
+                (SEG6_LOCAL_ACTION, 4) => {
+                    action = Some(u32::from_ne_bytes(value.try_into().unwrap()))
+                }
+                (SEG6_LOCAL_TABLE, 4) => {
+                    table = Some(u32::from_ne_bytes(value.try_into().unwrap()))
+                }
+                (SEG6_LOCAL_NH4, 4) => {
+                    nh4 = Some(Ipv4Addr::new(value[0], value[1], value[2], value[3]))
+                }
+                _ => (),
+            }
+        }
+    }
+
+    let action = match action? {
+        SEG6_LOCAL_ACTION_END => Seg6LocalAction::End,
+        SEG6_LOCAL_ACTION_END_DX4 => Seg6LocalAction::EndDx4 { nh4: nh4? },
+        SEG6_LOCAL_ACTION_END_DT6 => Seg6LocalAction::EndDt6 { table: table? },
+        _ => return None,
+    };
+
+    Some(RouteEncap::Seg6Local(action))
+}
+
+fn ip_tunnel(encap: &IpTunnelEncap) -> (RouteLwEnCapType, Vec<RouteLwTunnelEncap>) {
+    let nla = |kind, value| RouteLwTunnelEncap::Other(DefaultNla::new(kind, value));
+    let addr = |addr: &IpAddr| match addr {
+        IpAddr::V4(addr) => addr.octets().to_vec(),
+        IpAddr::V6(addr) => addr.octets().to_vec(),
+    };
+
+    let mut nlas = vec![];
+    if let Some(id) = encap.id {
+        nlas.push(nla(LWTUNNEL_IP_ID, id.to_be_bytes().to_vec()));
+    }
+    if let Some(dst) = &encap.dst {
+        nlas.push(nla(LWTUNNEL_IP_DST, addr(dst)));
+    }
+    if let Some(src) = &encap.src {
+        nlas.push(nla(LWTUNNEL_IP_SRC, addr(src)));
+    }
+    if let Some(ttl) = encap.ttl {
+        nlas.push(nla(LWTUNNEL_IP_TTL, vec![ttl]));
+    }
+    if let Some(tos) = encap.tos {
+        nlas.push(nla(LWTUNNEL_IP_TOS, vec![tos]));
+    }
+    if encap.flags != 0 {
+        nlas.push(nla(LWTUNNEL_IP_FLAGS, encap.flags.to_be_bytes().to_vec()));
+    }
+
+    let ipv6 = encap.dst.or(encap.src).is_some_and(|addr| addr.is_ipv6());
+    let kind = if ipv6 {
+        RouteLwEnCapType::Ip6
+    } else {
+        RouteLwEnCapType::Ip
+    };
+
+    (kind, nlas)
+}
+
+fn parse_ip_tunnel(nlas: &[RouteLwTunnelEncap]) -> IpTunnelEncap {
+    let mut encap = IpTunnelEncap::default();
+    let addr = |value: Vec<u8>| match value.len() {
+        4 => <[u8; 4]>::try_from(value).ok().map(IpAddr::from),
+        16 => <[u8; 16]>::try_from(value).ok().map(IpAddr::from),
+        _ => None,
+    };
+
+    for nla in nlas {
+        if let RouteLwTunnelEncap::Other(nla) = nla {
+            let value = nla_value(nla);
+            match (nla.kind(), value.len()) {
+                (LWTUNNEL_IP_ID, 8) => {
+                    encap.id = Some(u64::from_be_bytes(value.try_into().unwrap()))
+                }
+                (LWTUNNEL_IP_DST, _) => encap.dst = addr(value),
+                (LWTUNNEL_IP_SRC, _) => encap.src = addr(value),
+                (LWTUNNEL_IP_TTL, 1) => encap.ttl = Some(value[0]),
+                (LWTUNNEL_IP_TOS, 1) => encap.tos = Some(value[0]),
+                (LWTUNNEL_IP_FLAGS, 2) => encap.flags = u16::from_be_bytes([value[0], value[1]]),
+                _ => (),
+            }
+        }
+    }
+
+    encap
+}
+
+/// Encodes `encap` as `RTA_ENCAP_TYPE` and `RTA_ENCAP`
+pub(super) fn push_encap(rt_msg: &mut RouteMessage, encap: &RouteEncap) -> io::Result<()> {
+    let (kind, nlas) = match encap {
+        RouteEncap::Mpls { labels, ttl } => {
+            let mut nlas = vec![RouteLwTunnelEncap::Mpls(RouteMplsIpTunnel::Destination(
+                mpls_labels(labels),
+            ))];
+            if let Some(ttl) = ttl {
+                nlas.push(RouteLwTunnelEncap::Mpls(RouteMplsIpTunnel::Ttl(*ttl)));
+            }
+            (RouteLwEnCapType::Mpls, nlas)
+        }
+        RouteEncap::Seg6 { mode, segments } => (
+            RouteLwEnCapType::Seg6,
+            vec![RouteLwTunnelEncap::Other(DefaultNla::new(
+                SEG6_IPTUNNEL_SRH,
+                seg6_iptunnel(*mode, segments)?,
+            ))],
+        ),
+        RouteEncap::Seg6Local(action) => (RouteLwEnCapType::Seg6Local, seg6_local(action)),
+        RouteEncap::Ip(encap) => ip_tunnel(encap),
+    };
+
+    rt_msg.attributes.push(RouteAttribute::EncapType(kind));
+    rt_msg.attributes.push(RouteAttribute::Encap(nlas));
+
+    Ok(())
+}
+
+/// `None` for encapsulations this crate does not model
+pub(super) fn parse_encap(
+    kind: RouteLwEnCapType,
+    nlas: &[RouteLwTunnelEncap],
+) -> Option<RouteEncap> {
+    match kind {
+        RouteLwEnCapType::Mpls => {
+            let mut mpls_labels = None;
+            let mut mpls_ttl = None;
+
+            for nla in nlas {
+                match nla {
+                    RouteLwTunnelEncap::Mpls(RouteMplsIpTunnel::Destination(labels)) => {
+                        mpls_labels = Some(labels.iter().map(|label| label.label).collect());
+                    }
+                    RouteLwTunnelEncap::Mpls(RouteMplsIpTunnel::Ttl(ttl)) => {
+                        mpls_ttl = Some(*ttl);
+                    }
+                    _ => (),
+                }
+            }
+
+            mpls_labels.map(|labels| RouteEncap::Mpls {
+                labels,
+                ttl: mpls_ttl,
+            })
+        }
+        RouteLwEnCapType::Seg6 => nlas.iter().find_map(|nla| match nla {
+            RouteLwTunnelEncap::Other(nla) if nla.kind() == SEG6_IPTUNNEL_SRH => {
+                parse_seg6_iptunnel(&nla_value(nla))
+            }
+            _ => None,
+        }),
+        RouteLwEnCapType::Seg6Local => parse_seg6_local(nlas),
+        RouteLwEnCapType::Ip | RouteLwEnCapType::Ip6 => Some(RouteEncap::Ip(parse_ip_tunnel(nlas))),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segments() -> Vec<Ipv6Addr> {
+        vec![
+            "fc00::1".parse().unwrap(),
+            "fc00::2".parse().unwrap(),
+            "fc00::3".parse().unwrap(),
+        ]
+    }
+
+    /// The SRH segment list, `buf` is the `seg6_iptunnel_encap` payload
+    fn srh_segments(buf: &[u8]) -> Vec<Ipv6Addr> {
+        buf[12..]
+            .chunks_exact(16)
+            .map(|segment| Ipv6Addr::from(<[u8; 16]>::try_from(segment).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn seg6_encap_layout() {
+        let buf = seg6_iptunnel(Seg6Mode::Encap, &segments()).unwrap();
+
+        assert_eq!(buf.len(), 4 + 8 + 3 * 16);
+        assert_eq!(buf[..4], SEG6_IPTUN_MODE_ENCAP.to_ne_bytes());
+        // nexthdr, hdrlen, type, segments_left, first_segment
+        assert_eq!(buf[4..9], [0, 6, IPV6_SRCRT_TYPE_4, 2, 2]);
+        let mut reversed = segments();
+        reversed.reverse();
+        assert_eq!(srh_segments(&buf), reversed);
+    }
+
+    #[test]
+    fn seg6_inline_layout() {
+        let buf = seg6_iptunnel(Seg6Mode::Inline, &segments()).unwrap();
+
+        assert_eq!(buf.len(), 4 + 8 + 4 * 16);
+        assert_eq!(buf[..4], SEG6_IPTUN_MODE_INLINE.to_ne_bytes());
+        assert_eq!(buf[4..9], [0, 8, IPV6_SRCRT_TYPE_4, 3, 3]);
+        // slot 0 is left for the final destination
+        let mut expected = vec![Ipv6Addr::UNSPECIFIED];
+        expected.extend(segments().iter().rev());
+        assert_eq!(srh_segments(&buf), expected);
+    }
+
+    #[test]
+    fn seg6_round_trip() {
+        for mode in [Seg6Mode::Encap, Seg6Mode::Inline] {
+            let buf = seg6_iptunnel(mode, &segments()).unwrap();
+            assert_eq!(
+                parse_seg6_iptunnel(&buf),
+                Some(RouteEncap::Seg6 {
+                    mode,
+                    segments: segments()
+                })
+            );
+
+            let buf = seg6_iptunnel(mode, &segments()[..1]).unwrap();
+            assert_eq!(
+                parse_seg6_iptunnel(&buf),
+                Some(RouteEncap::Seg6 {
+                    mode,
+                    segments: segments()[..1].to_vec()
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn seg6_needs_segments() {
+        for mode in [Seg6Mode::Encap, Seg6Mode::Inline] {
+            let err = seg6_iptunnel(mode, &[]).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn seg6_segment_limit() {
+        let segments = vec![Ipv6Addr::LOCALHOST; 128];
+
+        for (mode, max) in [(Seg6Mode::Encap, 127), (Seg6Mode::Inline, 126)] {
+            let buf = seg6_iptunnel(mode, &segments[..max]).unwrap();
+            assert_eq!(buf[5], 254);
+            assert_eq!(buf[7], 126);
+
+            let err = seg6_iptunnel(mode, &segments[..max + 1]).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn truncated_seg6_is_not_parsed() {
+        assert_eq!(parse_seg6_iptunnel(&[0; 8]), None);
+        assert_eq!(parse_seg6_iptunnel(&9u32.to_ne_bytes().repeat(4)), None);
+    }
+}
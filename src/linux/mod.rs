@@ -1,8 +1,11 @@
+mod lookup;
+mod lwtunnel;
+mod mpls;
+mod nexthop;
 mod rtmsg;
 
 use std::{
     io::{self, Read, Write},
-    net::IpAddr,
     os::fd::{AsRawFd, RawFd},
 };
 
@@ -12,24 +15,36 @@ use libc::{
     RTNLGRP_MPLS_ROUTE, SOCK_CLOEXEC, SOCK_RAW,
 };
 use netlink_packet_core::{
-    NetlinkHeader, NetlinkMessage, NetlinkPayload, NLM_F_ACK, NLM_F_CREATE, NLM_F_DUMP, NLM_F_EXCL,
-    NLM_F_REQUEST,
+    NetlinkBuffer, NetlinkDeserializable, NetlinkHeader, NetlinkMessage, NetlinkPayload,
+    NetlinkSerializable, NLM_F_ACK, NLM_F_APPEND, NLM_F_CREATE, NLM_F_DUMP, NLM_F_EXCL,
+    NLM_F_REPLACE, NLM_F_REQUEST,
 };
 use netlink_packet_route::{
-    route::{
-        RouteAddress, RouteAttribute, RouteHeader, RouteMessage, RouteProtocol, RouteScope,
-        RouteType,
-    },
+    route::{RouteAttribute, RouteMessage, RouteProtocol, RouteScope, RouteType},
     AddressFamily, RouteNetlinkMessage,
 };
 
-use crate::{syscall, Route, RouteAction, RouteChange};
+use crate::{
+    syscall, FlushReport, IpFamily, Route, RouteAction, RouteChange, RouteFilter, RouteOp,
+};
+use mpls::parse_mpls_route;
+pub use lookup::RouteLookup;
+pub use mpls::MplsRoute;
+use lwtunnel::push_encap;
+use rtmsg::{
+    auto_scope, delete_message, fixup_metrics, parse_route, push_expiry, push_metrics,
+    push_next_hops, route_flags, route_message,
+};
 
-pub struct RouteSock(RawFd);
+pub struct RouteSock {
+    fd: RawFd,
+    /// Sequence number of the last request sent
+    seq: u32,
+}
 
 impl AsRawFd for RouteSock {
     fn as_raw_fd(&self) -> RawFd {
-        self.0
+        self.fd
     }
 }
 
@@ -39,7 +54,7 @@ impl RouteSock {
             socket(AF_NETLINK, SOCK_RAW | SOCK_CLOEXEC, NETLINK_ROUTE)
         )?;
 
-        Ok(RouteSock(fd))
+        Ok(RouteSock { fd, seq: 0 })
     }
 
     fn bind(&mut self, local: sockaddr_nl) -> io::Result<()> {
@@ -91,368 +106,456 @@ impl Read for RouteSock {
 
 impl Drop for RouteSock {
     fn drop(&mut self) {
-        syscall!(close(self.0)).unwrap();
+        syscall!(close(self.fd)).unwrap();
     }
 }
 
-impl RouteAction for RouteSock {
-    fn add(&mut self, route: &Route) -> io::Result<()> {
-        let mut nl_hdr = NetlinkHeader::default();
-        nl_hdr.flags = NLM_F_REQUEST | NLM_F_EXCL | NLM_F_CREATE | NLM_F_ACK;
-        nl_hdr.sequence_number = 1;
+/// Requests written at once by `request_batch`, small enough for
+/// their acks to fit the socket receive buffer
+const BATCH_SIZE: usize = 128;
 
-        let mut rt_msg = RouteMessage::default();
-        rt_msg.header.table = RouteHeader::RT_TABLE_MAIN;
-        rt_msg.header.protocol = RouteProtocol::Boot;
-        rt_msg.header.scope = RouteScope::Universe;
-        rt_msg.header.kind = RouteType::Unicast;
-
-        match route.destination {
-            std::net::IpAddr::V4(addr) => {
-                rt_msg.header.address_family = AddressFamily::Inet;
-                rt_msg
-                    .attributes
-                    .push(RouteAttribute::Destination(RouteAddress::Inet(addr)));
-            }
-            std::net::IpAddr::V6(addr) => {
-                rt_msg.header.address_family = AddressFamily::Inet6;
-                rt_msg
-                    .attributes
-                    .push(RouteAttribute::Destination(RouteAddress::Inet6(addr)));
-            }
-        }
-        rt_msg.header.destination_prefix_length = route.prefix;
-
-        if let Some(gateway) = route.gateway {
-            match gateway {
-                std::net::IpAddr::V4(addr) => {
-                    rt_msg.header.address_family = AddressFamily::Inet;
-                    rt_msg
-                        .attributes
-                        .push(RouteAttribute::Gateway(RouteAddress::Inet(addr)));
-                }
-                std::net::IpAddr::V6(addr) => {
-                    rt_msg.header.address_family = AddressFamily::Inet6;
-                    rt_msg
-                        .attributes
-                        .push(RouteAttribute::Gateway(RouteAddress::Inet6(addr)));
-                }
-            }
-        }
+/// Appends `msg` with netlink header `flags` and sequence number `seq` to `buf`
+fn serialize_request<I: NetlinkSerializable>(buf: &mut Vec<u8>, msg: I, flags: u16, seq: u32) {
+    let mut nl_hdr = NetlinkHeader::default();
+    nl_hdr.flags = flags;
+    nl_hdr.sequence_number = seq;
 
-        if let Some(index) = route.ifindex {
-            rt_msg.header.scope = RouteScope::Link;
-            rt_msg.attributes.push(RouteAttribute::Oif(index));
-        }
+    let mut req = NetlinkMessage::new(nl_hdr, NetlinkPayload::InnerMessage(msg));
+    req.finalize();
 
-        let mut req = NetlinkMessage::new(
-            nl_hdr,
-            NetlinkPayload::from(RouteNetlinkMessage::NewRoute(rt_msg)),
-        );
-        req.finalize();
-
-        let mut buf = [0u8; 4096];
-        req.serialize(&mut buf[..req.buffer_len()]);
-
-        self.write(&buf[..req.buffer_len()])?;
-
-        let mut rbuf = [0u8; 4096];
-        let n = self.read(&mut rbuf)?;
-        let nlmsg = <NetlinkMessage<RouteNetlinkMessage>>::deserialize(&rbuf[..n]);
-        // println!("<<< {:?}", nlmsg);
-        match nlmsg {
-            Ok(nlmsg) => {
-                if let NetlinkPayload::Error(e) = nlmsg.payload {
-                    match e.code {
-                        Some(e) => {
-                            return Err(io::Error::new(io::ErrorKind::Other, format!("{e:?}")))
-                        }
-                        None => return Ok(()),
-                    }
-                }
-            }
-            Err(e) => return Err(io::Error::new(io::ErrorKind::Other, format!("{e:?}"))),
+    let offset = buf.len();
+    buf.resize(offset + req.buffer_len(), 0);
+    req.serialize(&mut buf[offset..]);
+}
+
+impl RouteSock {
+    /// Reserves `count` sequence numbers and returns the first of them.
+    /// They only go up, so late replies to an earlier request can not be
+    /// taken for those of a newer one. 0 is left to kernel notifications.
+    fn next_seqs(&mut self, count: u32) -> u32 {
+        if self.seq.checked_add(count).is_none() {
+            self.seq = 0;
         }
+        let first = self.seq + 1;
+        self.seq += count;
+        first
+    }
 
-        Ok(())
+    fn request<I: NetlinkSerializable>(&mut self, msg: I, flags: u16) -> io::Result<()> {
+        let seq = self.next_seqs(1);
+        let mut buf = vec![];
+        serialize_request(&mut buf, msg, flags, seq);
+        // println!(">>> {:?}", buf);
+        self.write_all(&buf)
     }
 
-    fn delete(&mut self, route: &Route) -> io::Result<()> {
-        let mut nl_hdr = NetlinkHeader::default();
-        nl_hdr.flags = NLM_F_REQUEST | NLM_F_ACK;
-        nl_hdr.sequence_number = 1;
+    /// Sends `msgs` along with their netlink header flags, each with its
+    /// own sequence number and `NLM_F_REQUEST | NLM_F_ACK` added. Returns
+    /// the ack of each message in order, an `Err` of the outer result
+    /// means the socket itself failed.
+    fn request_batch<I: NetlinkSerializable>(
+        &mut self,
+        msgs: Vec<(I, u16)>,
+    ) -> io::Result<Vec<io::Result<()>>> {
+        let mut ret = Vec::with_capacity(msgs.len());
+        let mut msgs = msgs.into_iter();
 
-        let mut rt_msg = RouteMessage::default();
-        rt_msg.header.table = RouteHeader::RT_TABLE_MAIN;
-        rt_msg.header.scope = RouteScope::NoWhere;
-
-        match route.destination {
-            std::net::IpAddr::V4(addr) => {
-                rt_msg.header.address_family = AddressFamily::Inet;
-                rt_msg
-                    .attributes
-                    .push(RouteAttribute::Destination(RouteAddress::Inet(addr)));
+        loop {
+            let chunk: Vec<_> = msgs.by_ref().take(BATCH_SIZE).collect();
+            if chunk.is_empty() {
+                return Ok(ret);
             }
-            std::net::IpAddr::V6(addr) => {
-                rt_msg.header.address_family = AddressFamily::Inet6;
-                rt_msg
-                    .attributes
-                    .push(RouteAttribute::Destination(RouteAddress::Inet6(addr)));
+
+            let count = chunk.len() as u32;
+            let first = self.next_seqs(count);
+            let mut buf = vec![];
+            for (seq, (msg, flags)) in (first..).zip(chunk) {
+                serialize_request(&mut buf, msg, NLM_F_REQUEST | NLM_F_ACK | flags, seq);
             }
-        }
-        rt_msg.header.destination_prefix_length = route.prefix;
-
-        let mut req = NetlinkMessage::new(
-            nl_hdr,
-            NetlinkPayload::from(RouteNetlinkMessage::DelRoute(rt_msg)),
-        );
-        req.finalize();
-
-        let mut buf = [0u8; 4096];
-        req.serialize(&mut buf[..req.buffer_len()]);
-        self.write(&buf[..req.buffer_len()])?;
-
-        let mut rbuf = [0u8; 4096];
-        let n = self.read(&mut rbuf)?;
-        let nlmsg = <NetlinkMessage<RouteNetlinkMessage>>::deserialize(&rbuf[..n]);
-        // println!("<<< {:?}", nlmsg);
-        match nlmsg {
-            Ok(nlmsg) => {
-                if let NetlinkPayload::Error(e) = nlmsg.payload {
-                    match e.code {
-                        Some(e) => {
-                            return Err(io::Error::new(io::ErrorKind::Other, format!("{e:?}")))
+            self.write_all(&buf)?;
+
+            let mut acks: Vec<Option<io::Result<()>>> = (0..count).map(|_| None).collect();
+            let mut pending = count;
+            self.recv_replies(
+                first,
+                count,
+                |seq, payload: NetlinkPayload<RouteNetlinkMessage>| {
+                    if let NetlinkPayload::Error(e) = payload {
+                        let ack = &mut acks[(seq - first) as usize];
+                        if ack.is_none() {
+                            *ack = Some(match e.code {
+                                Some(_) => Err(e.to_io()),
+                                None => Ok(()),
+                            });
+                            pending -= 1;
                         }
-                        None => return Ok(()),
                     }
+                    (pending == 0).then_some(Ok(()))
+                },
+            )?;
+
+            ret.extend(acks.into_iter().flatten());
+        }
+    }
+
+    /// Reads the replies to the requests numbered `first` to
+    /// `first + count - 1` and hands each of them to `f` until it returns
+    /// a result. Anything else, like the acks left over by a request that
+    /// gave up early, is skipped.
+    fn recv_replies<I, T>(
+        &mut self,
+        first: u32,
+        count: u32,
+        mut f: impl FnMut(u32, NetlinkPayload<I>) -> Option<io::Result<T>>,
+    ) -> io::Result<T>
+    where
+        I: NetlinkDeserializable,
+    {
+        let mut rbuf = Self::new_buf();
+
+        loop {
+            let n = self.read(&mut rbuf)?;
+            fixup_metrics(&mut rbuf[..n]);
+            let mut offset = 0;
+
+            while offset < n {
+                let header = NetlinkBuffer::new_checked(&rbuf[offset..n])
+                    .map_err(|e| io::Error::other(format!("{e:?}")))?;
+                let length = header.length() as usize;
+                let seq = header.sequence_number();
+
+                if seq.wrapping_sub(first) < count {
+                    let nlmsg = <NetlinkMessage<I>>::deserialize(&rbuf[offset..n])
+                        .map_err(|e| io::Error::other(format!("{e:?}")))?;
+                    if let Some(ret) = f(seq, nlmsg.payload) {
+                        return ret;
+                    }
+                }
+
+                if length == 0 {
+                    break;
                 }
+                offset += length;
             }
-            Err(e) => return Err(io::Error::new(io::ErrorKind::Other, format!("{e:?}"))),
         }
+    }
 
-        Ok(())
+    /// Reads the ack of the last request
+    fn recv_ack(&mut self) -> io::Result<()> {
+        self.recv_replies(
+            self.seq,
+            1,
+            |_, payload: NetlinkPayload<RouteNetlinkMessage>| match payload {
+                NetlinkPayload::Error(e) if e.code.is_some() => Some(Err(e.to_io())),
+                NetlinkPayload::Error(_) => Some(Ok(())),
+                _ => None,
+            },
+        )
+    }
+
+    /// Reads the reply to a non-dump `RTM_GETROUTE`
+    fn recv_route(&mut self) -> io::Result<RouteMessage> {
+        self.recv_replies(self.seq, 1, |_, payload| match payload {
+            NetlinkPayload::InnerMessage(RouteNetlinkMessage::NewRoute(rt_msg)) => Some(Ok(rt_msg)),
+            NetlinkPayload::Error(e) if e.code.is_some() => Some(Err(e.to_io())),
+            payload => Some(Err(io::Error::other(format!(
+                "unexpected reply: {payload:?}"
+            )))),
+        })
+    }
+
+    /// Reads the routes of a dump reply
+    fn recv_dump(&mut self) -> io::Result<Vec<RouteMessage>> {
+        Ok(self
+            .recv_multipart::<RouteNetlinkMessage>()?
+            .into_iter()
+            .filter_map(|msg| match msg {
+                RouteNetlinkMessage::NewRoute(rt_msg) => Some(rt_msg),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Reads the multipart reply to the last request until `NLMSG_DONE`
+    fn recv_multipart<I: NetlinkDeserializable>(&mut self) -> io::Result<Vec<I>> {
+        let mut ret = vec![];
+
+        self.recv_replies(self.seq, 1, |_, payload| match payload {
+            NetlinkPayload::Done(_) => Some(Ok(std::mem::take(&mut ret))),
+            NetlinkPayload::Error(e) if e.code.is_some() => Some(Err(e.to_io())),
+            NetlinkPayload::InnerMessage(msg) => {
+                ret.push(msg);
+                None
+            }
+            _ => None,
+        })
+    }
+}
+
+/// `RTM_NEWROUTE` body installing `route`
+fn new_message(route: &Route) -> io::Result<RouteMessage> {
+    let mut rt_msg = route_message(route);
+    rt_msg.header.protocol = route
+        .protocol
+        .map(RouteProtocol::from)
+        .unwrap_or(RouteProtocol::Boot);
+    let scope = route.scope.unwrap_or_else(|| auto_scope(route));
+    rt_msg.header.scope = RouteScope::from(u8::from(scope));
+    rt_msg.header.kind = RouteType::from(u8::from(route.kind));
+    rt_msg.header.flags = route_flags(route.flags);
+
+    if let Some(gateway) = route.gateway {
+        rt_msg.attributes.push(rtmsg::gateway_attribute(
+            rt_msg.header.address_family,
+            &gateway,
+        ));
+    }
+
+    if let Some(index) = route.ifindex {
+        rt_msg.attributes.push(RouteAttribute::Oif(index));
+    }
+
+    push_next_hops(&mut rt_msg, &route.next_hops);
+    push_metrics(&mut rt_msg, &route.metrics);
+    push_expiry(&mut rt_msg, route)?;
+    if let Some(encap) = &route.encap {
+        push_encap(&mut rt_msg, encap)?;
+    }
+
+    Ok(rt_msg)
+}
+
+/// Request and netlink header flags carrying out `op`
+fn op_message(op: &RouteOp) -> io::Result<(RouteNetlinkMessage, u16)> {
+    Ok(match op {
+        RouteOp::Add(route) => (
+            RouteNetlinkMessage::NewRoute(new_message(route)?),
+            NLM_F_EXCL | NLM_F_CREATE,
+        ),
+        RouteOp::Replace(route) => (
+            RouteNetlinkMessage::NewRoute(new_message(route)?),
+            NLM_F_CREATE | NLM_F_REPLACE,
+        ),
+        RouteOp::Delete(route) => (RouteNetlinkMessage::DelRoute(delete_message(route)), 0),
+    })
+}
+
+impl RouteSock {
+    /// Sends `route` as `RTM_NEWROUTE`, `flags` decide whether an existing
+    /// route is an error, gets replaced or is required
+    fn new_route(&mut self, route: &Route, flags: u16) -> io::Result<()> {
+        self.request(
+            RouteNetlinkMessage::NewRoute(new_message(route)?),
+            NLM_F_REQUEST | NLM_F_ACK | flags,
+        )?;
+        self.recv_ack()
+    }
+}
+
+impl RouteAction for RouteSock {
+    fn add(&mut self, route: &Route) -> io::Result<()> {
+        self.new_route(route, NLM_F_EXCL | NLM_F_CREATE)
+    }
+
+    fn replace(&mut self, route: &Route) -> io::Result<()> {
+        self.new_route(route, NLM_F_CREATE | NLM_F_REPLACE)
+    }
+
+    fn change(&mut self, route: &Route) -> io::Result<()> {
+        self.new_route(route, NLM_F_REPLACE)
+    }
+
+    fn append(&mut self, route: &Route) -> io::Result<()> {
+        self.new_route(route, NLM_F_CREATE | NLM_F_APPEND)
+    }
+
+    fn delete(&mut self, route: &Route) -> io::Result<()> {
+        self.request(
+            RouteNetlinkMessage::DelRoute(delete_message(route)),
+            NLM_F_REQUEST | NLM_F_ACK,
+        )?;
+        self.recv_ack()
     }
 
     fn get(&mut self, route: &Route) -> io::Result<Route> {
-        let mut nl_hdr = NetlinkHeader::default();
-        nl_hdr.flags = NLM_F_DUMP | NLM_F_REQUEST;
-        nl_hdr.sequence_number = 1;
+        let table = route.table.unwrap_or(rtmsg::RT_TABLE_MAIN);
 
         let mut rt_msg = RouteMessage::default();
-        rt_msg.header.address_family = AddressFamily::Inet;
-        rt_msg
-            .attributes
-            .push(RouteAttribute::Table(254 /*table main*/));
+        rt_msg.header.address_family = rtmsg::family(&route.destination);
+        rt_msg.attributes.push(RouteAttribute::Table(table));
         if let Some(index) = route.ifindex {
             rt_msg.attributes.push(RouteAttribute::Oif(index));
         }
 
-        let mut req = NetlinkMessage::new(
-            nl_hdr,
-            NetlinkPayload::from(RouteNetlinkMessage::GetRoute(rt_msg)),
-        );
-        req.finalize();
-        let mut buf = [0u8; 4096];
-        req.serialize(&mut buf[..req.buffer_len()]);
-        // println!(">>> {:?}", &buf[..req.buffer_len()]);
-        self.write(&buf[..req.buffer_len()])?;
-
-        let mut ret = Route::default();
-        let mut offset = 0;
-        let mut rbuf = [0u8; 4096];
-        let n = self.read(&mut rbuf)?;
-        loop {
-            let bytes = &rbuf[offset..];
+        self.request(
+            RouteNetlinkMessage::GetRoute(rt_msg),
+            NLM_F_DUMP | NLM_F_REQUEST,
+        )?;
+
+        let mut ret: Option<Route> = None;
+        for rt_msg in self.recv_dump()? {
+            let t_route = parse_route(&rt_msg);
+
+            // the kernel ignores the table attribute on dump requests
+            if t_route.table != Some(table) || t_route.prefix > route.prefix {
+                continue;
+            }
 
-            let nlmsg = match <NetlinkMessage<RouteNetlinkMessage>>::deserialize(bytes) {
-                Ok(nlmsg) => {
-                    nlmsg
+            let contained = IpNetwork::new(t_route.destination, t_route.prefix)
+                .map(|net| net.contains(route.destination))
+                .unwrap_or(false);
+            if !contained {
+                continue;
+            }
+
+            // source-specific routes only apply to the sources they cover
+            if let Some((source, prefix)) = t_route.source {
+                let covered = route.source.is_some_and(|(from, _)| {
+                    IpNetwork::new(source, prefix)
+                        .map(|net| net.contains(from))
+                        .unwrap_or(false)
+                });
+                if prefix > 0 && !covered {
+                    continue;
                 }
-                Err(e) => {
-                    return Err(io::Error::new(io::ErrorKind::Other, format!("{e:?}")));
+            }
+
+            // longest prefix wins, equal prefixes are decided by the lower metric
+            let better = match &ret {
+                None => true,
+                Some(best) => {
+                    t_route.prefix > best.prefix
+                        || (t_route.prefix == best.prefix
+                            && t_route.metric.unwrap_or(0) < best.metric.unwrap_or(0))
                 }
             };
-
-            if matches!(nlmsg.payload, NetlinkPayload::Done(_)) {
-                // println!("Done!");
-                break;
+            if better {
+                ret = Some(t_route);
             }
+        }
 
-            if let NetlinkPayload::InnerMessage(rtnl_msg) = nlmsg.payload {
-                if let RouteNetlinkMessage::NewRoute(rt_msg) = rtnl_msg {
-                    if rt_msg.header.destination_prefix_length <= route.prefix
-                        && rt_msg.header.destination_prefix_length >= ret.prefix
-                    {
-                        let mut t_route = Route::default();
-                        t_route.prefix = rt_msg.header.destination_prefix_length;
-
-                        let mut travel_over_nomal = true;
-                        for attr in &rt_msg.attributes {
-                            match attr {
-                                RouteAttribute::Destination(dst) => {
-                                    if let RouteAddress::Inet(dst) = dst {
-                                        if let IpAddr::V4(target) = route.destination {
-                                            let contained = IpNetwork::new(
-                                                IpAddr::V4(*dst),
-                                                rt_msg.header.destination_prefix_length,
-                                            )
-                                            .unwrap()
-                                            .contains(IpAddr::V4(target));
-
-                                            if !contained {
-                                                travel_over_nomal = false;
-                                                break;
-                                            }
-
-                                            t_route.destination = IpAddr::V4(*dst)
-                                        }
-                                    } else if let RouteAddress::Inet6(dst) = dst {
-                                        if let IpAddr::V6(target) = route.destination {
-                                            let contained = IpNetwork::new(
-                                                IpAddr::V6(*dst),
-                                                rt_msg.header.destination_prefix_length,
-                                            )
-                                            .unwrap()
-                                            .contains(IpAddr::V6(target));
-
-                                            if !contained {
-                                                travel_over_nomal = false;
-                                                break;
-                                            }
-
-                                            t_route.destination = IpAddr::V6(*dst)
-                                        }
-                                    }
-                                }
-
-                                RouteAttribute::Gateway(gw) => {
-                                    if let RouteAddress::Inet(gw) = gw {
-                                        t_route.gateway = Some(IpAddr::V4(*gw));
-                                    } else if let RouteAddress::Inet6(gw) = gw {
-                                        t_route.gateway = Some(IpAddr::V6(*gw));
-                                    } else {
-                                        return Err(io::Error::new(
-                                            io::ErrorKind::Other,
-                                            "Invalid gateway",
-                                        ));
-                                    }
-                                }
-
-                                RouteAttribute::Oif(index) => {
-                                    t_route.ifindex = Some(*index);
-                                }
-
-                                _ => (),
-                            }
-                        }
+        Ok(ret.unwrap_or_default())
+    }
 
-                        if travel_over_nomal {
-                            ret = t_route
-                        }
-                    }
-                }
+    fn list(&mut self, filter: &RouteFilter) -> io::Result<Vec<Route>> {
+        let mut rt_msg = RouteMessage::default();
+        rt_msg.header.address_family = match filter.family {
+            Some(IpFamily::V4) => AddressFamily::Inet,
+            Some(IpFamily::V6) => AddressFamily::Inet6,
+            None => AddressFamily::Unspec,
+        };
+
+        self.request(
+            RouteNetlinkMessage::GetRoute(rt_msg),
+            NLM_F_DUMP | NLM_F_REQUEST,
+        )?;
+
+        Ok(self
+            .recv_dump()?
+            .iter()
+            // an unspecified family dumps MPLS routes too
+            .filter(|rt_msg| {
+                matches!(
+                    rt_msg.header.address_family,
+                    AddressFamily::Inet | AddressFamily::Inet6
+                )
+            })
+            .map(parse_route)
+            .filter(|route| filter.matches(route))
+            .collect())
+    }
+
+    fn flush(&mut self, filter: &RouteFilter) -> io::Result<FlushReport> {
+        let routes = self.list(filter)?;
+        let msgs = routes
+            .iter()
+            .map(|route| (RouteNetlinkMessage::DelRoute(delete_message(route)), 0))
+            .collect();
+
+        let mut report = FlushReport::default();
+        for (route, ret) in routes.into_iter().zip(self.request_batch(msgs)?) {
+            match ret {
+                Ok(()) => report.removed += 1,
+                Err(e) => report.failed.push((route, e)),
             }
+        }
+
+        Ok(report)
+    }
 
-            offset += nlmsg.header.length as usize;
-            if offset == n || nlmsg.header.length == 0 {
-                // println!("offset: {}, n: {}, nlmsg len: {}", offset, n, nlmsg.header.length);
-                break;
+    fn batch(&mut self, ops: &[RouteOp]) -> io::Result<Vec<io::Result<()>>> {
+        // routes which can not be encoded fail on their own, the rest is sent
+        let mut ret = Vec::with_capacity(ops.len());
+        let mut msgs = vec![];
+        for op in ops {
+            match op_message(op) {
+                Ok(msg) => {
+                    msgs.push(msg);
+                    ret.push(None);
+                }
+                Err(e) => ret.push(Some(Err(e))),
             }
         }
 
-        Ok(ret)
+        let mut acks = self.request_batch(msgs)?.into_iter();
+        Ok(ret
+            .into_iter()
+            .map(|ret| {
+                ret.or_else(|| acks.next())
+                    .unwrap_or_else(|| Err(io::Error::other("missing ack")))
+            })
+            .collect())
     }
 
     fn monitor(&mut self, buf: &mut [u8]) -> io::Result<(RouteChange, Route)> {
-        // maybe have another netlink message on same buf. see `get`
+        loop {
+            if let RouteEvent::Ip(change, route) = self.monitor_event(buf)? {
+                return Ok((change, route));
+            }
+        }
+    }
+}
+
+/// A route change read by `RouteSock::monitor_event`
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
+pub enum RouteEvent {
+    Ip(RouteChange, Route),
+    Mpls(RouteChange, MplsRoute),
+}
+
+impl RouteSock {
+    /// Like `RouteAction::monitor`, but MPLS route changes are reported too
+    /// instead of being skipped.
+    pub fn monitor_event(&mut self, buf: &mut [u8]) -> io::Result<RouteEvent> {
+        // maybe have another netlink message on same buf. see `recv_dump`
         let n = self.read(buf)?;
-        println!("read {n} bytes");
+        fixup_metrics(&mut buf[..n]);
+
+        let nlmsg = <NetlinkMessage<RouteNetlinkMessage>>::deserialize(&buf[..n])
+            .map_err(|e| io::Error::other(format!("{e:?}")))?;
 
-        let nlmsg = match <NetlinkMessage<RouteNetlinkMessage>>::deserialize(&buf[..n]) {
-            Ok(nlmsg) => {
-                nlmsg
+        let (change, rt_msg) = match nlmsg.payload {
+            NetlinkPayload::InnerMessage(RouteNetlinkMessage::NewRoute(rt_msg)) => {
+                (RouteChange::ADD, rt_msg)
             }
-            Err(e) => {
-                return Err(io::Error::new(io::ErrorKind::Other, format!("{e:?}")));
+            NetlinkPayload::InnerMessage(RouteNetlinkMessage::DelRoute(rt_msg)) => {
+                (RouteChange::DELETE, rt_msg)
             }
+            NetlinkPayload::InnerMessage(rtnl_msg) => {
+                return Err(io::Error::other(format!(
+                    "Unexpected rtnl message: {:?}",
+                    rtnl_msg
+                )))
+            }
+            _ => return Err(io::Error::other(format!("not rtnl message: {:?}", nlmsg))),
         };
 
-        let mut ret = Route::default();
-
-        if let NetlinkPayload::InnerMessage(rtnl_msg) = nlmsg.payload {
-            match rtnl_msg {
-                RouteNetlinkMessage::NewRoute(rtmsg) => {
-                    ret.prefix = rtmsg.header.destination_prefix_length;
-
-                    for attr in &rtmsg.attributes {
-                        match attr {
-                            RouteAttribute::Destination(addr) => {
-                                if let RouteAddress::Inet(addr) = addr {
-                                    ret.destination = IpAddr::V4(*addr);
-                                } else if let RouteAddress::Inet6(addr) = addr {
-                                    ret.destination = IpAddr::V6(*addr);
-                                }
-                            },
-                            RouteAttribute::Gateway(addr) => {
-                                if let RouteAddress::Inet(addr) = addr {
-                                    ret.gateway = Some(IpAddr::V4(*addr));
-                                } else if let RouteAddress::Inet6(addr) = addr {
-                                    ret.gateway = Some(IpAddr::V6(*addr));
-                                }
-
-                            },
-                            RouteAttribute::Oif(ifindex) => {
-                                ret.ifindex = Some(*ifindex);
-                            },
-                            _ => (),
-                        }
-                    }
-
-                    return Ok((RouteChange::ADD, ret))
-                },
-                RouteNetlinkMessage::DelRoute(rtmsg) => {
-                    ret.prefix = rtmsg.header.destination_prefix_length;
-
-                    for attr in &rtmsg.attributes {
-                        match attr {
-                            RouteAttribute::Destination(addr) => {
-                                if let RouteAddress::Inet(addr) = addr {
-                                    ret.destination = IpAddr::V4(*addr);
-                                } else if let RouteAddress::Inet6(addr) = addr {
-                                    ret.destination = IpAddr::V6(*addr);
-                                }
-                            },
-                            RouteAttribute::Gateway(addr) => {
-                                if let RouteAddress::Inet(addr) = addr {
-                                    ret.gateway = Some(IpAddr::V4(*addr));
-                                } else if let RouteAddress::Inet6(addr) = addr {
-                                    ret.gateway = Some(IpAddr::V6(*addr));
-                                }
-
-                            },
-                            RouteAttribute::Oif(ifindex) => {
-                                ret.ifindex = Some(*ifindex);
-                            },
-                            _ => (),
-                        }
-                    }
-
-                    return Ok((RouteChange::DELETE, ret))
-                },
-                _ => {
-                    return Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("Unexpected rtnl message: {:?}", rtnl_msg),
-                    ))
-                },
-            }
+        if rt_msg.header.address_family == AddressFamily::Mpls {
+            Ok(RouteEvent::Mpls(change, parse_mpls_route(&rt_msg)))
         } else {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!("not rtnl message: {:?}", nlmsg),
-            ))
+            Ok(RouteEvent::Ip(change, parse_route(&rt_msg)))
         }
-
     }
 }
 
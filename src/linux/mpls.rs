@@ -0,0 +1,191 @@
+use std::{io, net::IpAddr};
+
+use netlink_packet_core::{NLM_F_ACK, NLM_F_CREATE, NLM_F_DUMP, NLM_F_EXCL, NLM_F_REQUEST};
+use netlink_packet_route::{
+    route::{
+        MplsLabel, RouteAddress, RouteAttribute, RouteHeader, RouteMessage, RouteProtocol,
+        RouteScope, RouteType, RouteVia,
+    },
+    AddressFamily, RouteNetlinkMessage,
+};
+
+use super::{rtmsg::mpls_labels, RouteSock};
+use crate::if_nametoindex;
+
+/// MPLS label route (`AF_MPLS`). Packets arriving with `label` get it
+/// swapped for `new_labels`, or popped if `new_labels` is empty.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MplsRoute {
+    /// Incoming label
+    pub label: u32,
+    /// Outgoing label stack (`RTA_NEWDST`), outermost label first
+    pub new_labels: Vec<u32>,
+    /// Next hop (`RTA_VIA`)
+    pub via: Option<IpAddr>,
+    pub ifindex: Option<u32>,
+}
+
+impl MplsRoute {
+    pub fn new(label: u32) -> MplsRoute {
+        MplsRoute {
+            label,
+            ..Default::default()
+        }
+    }
+
+    pub fn new_labels(mut self, labels: Vec<u32>) -> MplsRoute {
+        self.new_labels = labels;
+        self
+    }
+
+    pub fn via(mut self, via: IpAddr) -> MplsRoute {
+        self.via = Some(via);
+        self
+    }
+
+    pub fn ifindex(mut self, ifindex: u32) -> MplsRoute {
+        self.ifindex = Some(ifindex);
+        self
+    }
+
+    pub fn interface(mut self, interface: &str) -> MplsRoute {
+        self.ifindex = if_nametoindex(interface);
+        self
+    }
+}
+
+/// The kernel refuses MPLS adds and deletes unless they are universe
+/// scope unicast routes, like iproute2 sends them
+fn mpls_message(route: &MplsRoute) -> RouteMessage {
+    let mut rt_msg = RouteMessage::default();
+    rt_msg.header.address_family = AddressFamily::Mpls;
+    rt_msg.header.destination_prefix_length = 20;
+    rt_msg.header.table = RouteHeader::RT_TABLE_MAIN;
+    rt_msg.header.scope = RouteScope::Universe;
+    rt_msg.header.kind = RouteType::Unicast;
+
+    rt_msg
+        .attributes
+        .push(RouteAttribute::Destination(RouteAddress::Mpls(MplsLabel {
+            label: route.label,
+            traffic_class: 0,
+            bottom_of_stack: true,
+            ttl: 0,
+        })));
+
+    if !route.new_labels.is_empty() {
+        rt_msg
+            .attributes
+            .push(RouteAttribute::NewDestination(mpls_labels(
+                &route.new_labels,
+            )));
+    }
+
+    if let Some(via) = route.via {
+        let via = match via {
+            IpAddr::V4(addr) => RouteVia::Inet(addr),
+            IpAddr::V6(addr) => RouteVia::Inet6(addr),
+        };
+        rt_msg.attributes.push(RouteAttribute::Via(via));
+    }
+
+    if let Some(ifindex) = route.ifindex {
+        rt_msg.attributes.push(RouteAttribute::Oif(ifindex));
+    }
+
+    rt_msg
+}
+
+pub(super) fn parse_mpls_route(rt_msg: &RouteMessage) -> MplsRoute {
+    let mut route = MplsRoute::default();
+
+    for attr in &rt_msg.attributes {
+        match attr {
+            RouteAttribute::Destination(RouteAddress::Mpls(label)) => {
+                route.label = label.label;
+            }
+            RouteAttribute::NewDestination(labels) => {
+                route.new_labels = labels.iter().map(|label| label.label).collect();
+            }
+            RouteAttribute::Via(RouteVia::Inet(addr)) => {
+                route.via = Some(IpAddr::V4(*addr));
+            }
+            RouteAttribute::Via(RouteVia::Inet6(addr)) => {
+                route.via = Some(IpAddr::V6(*addr));
+            }
+            RouteAttribute::Oif(ifindex) => {
+                route.ifindex = Some(*ifindex);
+            }
+            _ => (),
+        }
+    }
+
+    route
+}
+
+impl RouteSock {
+    pub fn add_mpls(&mut self, route: &MplsRoute) -> io::Result<()> {
+        let mut rt_msg = mpls_message(route);
+        rt_msg.header.protocol = RouteProtocol::Boot;
+
+        self.request(
+            RouteNetlinkMessage::NewRoute(rt_msg),
+            NLM_F_REQUEST | NLM_F_EXCL | NLM_F_CREATE | NLM_F_ACK,
+        )?;
+        self.recv_ack()
+    }
+
+    pub fn delete_mpls(&mut self, route: &MplsRoute) -> io::Result<()> {
+        self.request(
+            RouteNetlinkMessage::DelRoute(mpls_message(route)),
+            NLM_F_REQUEST | NLM_F_ACK,
+        )?;
+        self.recv_ack()
+    }
+
+    pub fn list_mpls(&mut self) -> io::Result<Vec<MplsRoute>> {
+        let mut rt_msg = RouteMessage::default();
+        rt_msg.header.address_family = AddressFamily::Mpls;
+
+        self.request(
+            RouteNetlinkMessage::GetRoute(rt_msg),
+            NLM_F_DUMP | NLM_F_REQUEST,
+        )?;
+
+        Ok(self
+            .recv_dump()?
+            .iter()
+            .filter(|rt_msg| rt_msg.header.address_family == AddressFamily::Mpls)
+            .map(parse_mpls_route)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use netlink_packet_utils::Emitable;
+
+    use super::*;
+
+    #[test]
+    fn mpls_header() {
+        let rt_msg = mpls_message(&MplsRoute::new(100).new_labels(vec![200]));
+
+        let mut buf = vec![0u8; rt_msg.header.buffer_len()];
+        rt_msg.header.emit(&mut buf);
+        // family, dst_len, src_len, tos, table, protocol, scope, type
+        assert_eq!(
+            buf[..8],
+            [
+                libc::AF_MPLS as u8,
+                20,
+                0,
+                0,
+                libc::RT_TABLE_MAIN,
+                0,
+                libc::RT_SCOPE_UNIVERSE,
+                libc::RTN_UNICAST,
+            ]
+        );
+    }
+}
@@ -0,0 +1,489 @@
+use std::{io, net::IpAddr};
+
+use netlink_packet_core::{
+    NetlinkDeserializable, NetlinkHeader, NetlinkSerializable, NLM_F_ACK, NLM_F_CREATE, NLM_F_DUMP,
+    NLM_F_EXCL, NLM_F_REPLACE, NLM_F_REQUEST,
+};
+use netlink_packet_route::route::RouteProtocol;
+use netlink_packet_utils::{
+    nla::{DefaultNla, Nla, NlasIterator, NLA_F_NESTED},
+    traits::Emitable,
+    DecodeError,
+};
+
+use super::{lwtunnel::nla_value, rtmsg::user_hz, RouteSock};
+use crate::{NextHopFlags, NexthopAction, NexthopGroupMember, NexthopObject, ResilientGroup};
+
+// netlink-packet-route does not know about nexthop objects yet
+const RTM_NEWNEXTHOP: u16 = 104;
+const RTM_DELNEXTHOP: u16 = 105;
+const RTM_GETNEXTHOP: u16 = 106;
+
+const NHA_ID: u16 = 1;
+const NHA_GROUP: u16 = 2;
+const NHA_GROUP_TYPE: u16 = 3;
+const NHA_BLACKHOLE: u16 = 4;
+const NHA_OIF: u16 = 5;
+const NHA_GATEWAY: u16 = 6;
+const NHA_RES_GROUP: u16 = 12;
+
+const NHA_RES_GROUP_BUCKETS: u16 = 1;
+const NHA_RES_GROUP_IDLE_TIMER: u16 = 2;
+const NHA_RES_GROUP_UNBALANCED_TIMER: u16 = 3;
+
+const NEXTHOP_GRP_TYPE_RES: u16 = 1;
+
+const NHMSG_LEN: usize = 8;
+
+/// `struct nhmsg` followed by its `NHA_*` attributes
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(super) struct NexthopMessage {
+    message_type: u16,
+    family: u8,
+    scope: u8,
+    protocol: u8,
+    flags: u32,
+    attributes: Vec<DefaultNla>,
+}
+
+impl NetlinkSerializable for NexthopMessage {
+    fn message_type(&self) -> u16 {
+        self.message_type
+    }
+
+    fn buffer_len(&self) -> usize {
+        NHMSG_LEN + self.attributes.as_slice().buffer_len()
+    }
+
+    fn serialize(&self, buffer: &mut [u8]) {
+        buffer[0] = self.family;
+        buffer[1] = self.scope;
+        buffer[2] = self.protocol;
+        buffer[3] = 0;
+        buffer[4..NHMSG_LEN].copy_from_slice(&self.flags.to_ne_bytes());
+        self.attributes.as_slice().emit(&mut buffer[NHMSG_LEN..]);
+    }
+}
+
+impl NetlinkDeserializable for NexthopMessage {
+    type Error = DecodeError;
+
+    fn deserialize(header: &NetlinkHeader, payload: &[u8]) -> Result<Self, Self::Error> {
+        if payload.len() < NHMSG_LEN {
+            return Err(DecodeError::from("nhmsg is too short"));
+        }
+
+        Ok(NexthopMessage {
+            message_type: header.message_type,
+            family: payload[0],
+            scope: payload[1],
+            protocol: payload[2],
+            flags: u32::from_ne_bytes(payload[4..NHMSG_LEN].try_into().unwrap()),
+            attributes: parse_nlas(&payload[NHMSG_LEN..])?,
+        })
+    }
+}
+
+/// Attribute kinds are returned without the nested and byte order flags
+fn parse_nlas(buf: &[u8]) -> Result<Vec<DefaultNla>, DecodeError> {
+    NlasIterator::new(buf)
+        .map(|nla| nla.map(|nla| DefaultNla::new(nla.kind(), nla.value().to_vec())))
+        .collect()
+}
+
+fn emit_nlas(nlas: &[DefaultNla]) -> Vec<u8> {
+    let mut buf = vec![0u8; nlas.buffer_len()];
+    nlas.emit(&mut buf);
+    buf
+}
+
+fn nexthop_message(nexthop: &NexthopObject) -> NexthopMessage {
+    let mut nh_msg = NexthopMessage {
+        message_type: RTM_NEWNEXTHOP,
+        protocol: nexthop
+            .protocol
+            .unwrap_or_else(|| u8::from(RouteProtocol::Boot)),
+        flags: nexthop.flags.0 as u32,
+        ..Default::default()
+    };
+    let nla = |kind, value| DefaultNla::new(kind, value);
+
+    if nexthop.id != 0 {
+        nh_msg
+            .attributes
+            .push(nla(NHA_ID, nexthop.id.to_ne_bytes().to_vec()));
+    }
+
+    if !nexthop.group.is_empty() {
+        // groups are family independent
+        nh_msg.family = libc::AF_UNSPEC as u8;
+
+        let mut group = Vec::with_capacity(8 * nexthop.group.len());
+        for member in &nexthop.group {
+            group.extend_from_slice(&member.id.to_ne_bytes());
+            // the weight minus one, like rtnh_hops
+            let weight = (member.weight.clamp(1, 256) - 1) as u8;
+            group.extend_from_slice(&[weight, 0, 0, 0]);
+        }
+        nh_msg.attributes.push(nla(NHA_GROUP, group));
+
+        if let Some(resilient) = &nexthop.resilient {
+            nh_msg.attributes.push(nla(
+                NHA_GROUP_TYPE,
+                NEXTHOP_GRP_TYPE_RES.to_ne_bytes().to_vec(),
+            ));
+            nh_msg.attributes.push(nla(
+                NHA_RES_GROUP | NLA_F_NESTED,
+                resilient_group(resilient),
+            ));
+        }
+
+        return nh_msg;
+    }
+
+    nh_msg.family = match (nexthop.gateway, nexthop.ipv6) {
+        (Some(IpAddr::V6(_)), _) | (None, true) => libc::AF_INET6 as u8,
+        _ => libc::AF_INET as u8,
+    };
+
+    if nexthop.blackhole {
+        nh_msg.attributes.push(nla(NHA_BLACKHOLE, vec![]));
+    }
+    if let Some(index) = nexthop.ifindex {
+        nh_msg
+            .attributes
+            .push(nla(NHA_OIF, index.to_ne_bytes().to_vec()));
+    }
+    match nexthop.gateway {
+        Some(IpAddr::V4(addr)) => nh_msg
+            .attributes
+            .push(nla(NHA_GATEWAY, addr.octets().to_vec())),
+        Some(IpAddr::V6(addr)) => nh_msg
+            .attributes
+            .push(nla(NHA_GATEWAY, addr.octets().to_vec())),
+        None => (),
+    }
+
+    nh_msg
+}
+
+/// `NHA_RES_GROUP` payload, the timers are sent in clock ticks
+fn resilient_group(resilient: &ResilientGroup) -> Vec<u8> {
+    let hz = user_hz();
+    let mut nlas = vec![];
+
+    if let Some(buckets) = resilient.buckets {
+        nlas.push(DefaultNla::new(
+            NHA_RES_GROUP_BUCKETS,
+            buckets.to_ne_bytes().to_vec(),
+        ));
+    }
+    if let Some(idle_timer) = resilient.idle_timer {
+        nlas.push(DefaultNla::new(
+            NHA_RES_GROUP_IDLE_TIMER,
+            idle_timer.saturating_mul(hz).to_ne_bytes().to_vec(),
+        ));
+    }
+    if let Some(unbalanced_timer) = resilient.unbalanced_timer {
+        nlas.push(DefaultNla::new(
+            NHA_RES_GROUP_UNBALANCED_TIMER,
+            unbalanced_timer.saturating_mul(hz).to_ne_bytes().to_vec(),
+        ));
+    }
+
+    emit_nlas(&nlas)
+}
+
+fn parse_resilient_group(buf: &[u8]) -> ResilientGroup {
+    let hz = user_hz();
+    let mut resilient = ResilientGroup::default();
+
+    for nla in parse_nlas(buf).unwrap_or_default() {
+        let value = nla_value(&nla);
+        match (nla.kind(), value.len()) {
+            (NHA_RES_GROUP_BUCKETS, 2) => {
+                resilient.buckets = Some(u16::from_ne_bytes([value[0], value[1]]))
+            }
+            (NHA_RES_GROUP_IDLE_TIMER, 4) => {
+                resilient.idle_timer = Some(u32::from_ne_bytes(value.try_into().unwrap()) / hz)
+            }
+            (NHA_RES_GROUP_UNBALANCED_TIMER, 4) => {
+                resilient.unbalanced_timer =
+                    Some(u32::from_ne_bytes(value.try_into().unwrap()) / hz)
+            }
+            _ => (),
+        }
+    }
+
+    resilient
+}
+
+fn parse_nexthop(nh_msg: &NexthopMessage) -> NexthopObject {
+    let mut nexthop = NexthopObject {
+        flags: NextHopFlags(nh_msg.flags as u8),
+        protocol: Some(nh_msg.protocol),
+        ipv6: nh_msg.family == libc::AF_INET6 as u8,
+        ..Default::default()
+    };
+    let mut resilient = None;
+    let mut group_type = 0;
+
+    for nla in &nh_msg.attributes {
+        let value = nla_value(nla);
+        match (nla.kind(), value.len()) {
+            (NHA_ID, 4) => nexthop.id = u32::from_ne_bytes(value.try_into().unwrap()),
+            (NHA_GROUP, _) => {
+                nexthop.group = value
+                    .chunks_exact(8)
+                    .map(|member| NexthopGroupMember {
+                        id: u32::from_ne_bytes(member[..4].try_into().unwrap()),
+                        weight: member[4] as u16 + 1,
+                    })
+                    .collect();
+            }
+            (NHA_GROUP_TYPE, 2) => group_type = u16::from_ne_bytes([value[0], value[1]]),
+            (NHA_RES_GROUP, _) => resilient = Some(parse_resilient_group(&value)),
+            (NHA_BLACKHOLE, _) => nexthop.blackhole = true,
+            (NHA_OIF, 4) => nexthop.ifindex = Some(u32::from_ne_bytes(value.try_into().unwrap())),
+            (NHA_GATEWAY, 4) => nexthop.gateway = <[u8; 4]>::try_from(value).ok().map(IpAddr::from),
+            (NHA_GATEWAY, 16) => {
+                nexthop.gateway = <[u8; 16]>::try_from(value).ok().map(IpAddr::from)
+            }
+            _ => (),
+        }
+    }
+
+    if group_type == NEXTHOP_GRP_TYPE_RES {
+        nexthop.resilient = Some(resilient.unwrap_or_default());
+    }
+
+    nexthop
+}
+
+impl NexthopAction for RouteSock {
+    fn add_nexthop(&mut self, nexthop: &NexthopObject) -> io::Result<()> {
+        self.request(
+            nexthop_message(nexthop),
+            NLM_F_REQUEST | NLM_F_EXCL | NLM_F_CREATE | NLM_F_ACK,
+        )?;
+        self.recv_ack()
+    }
+
+    fn replace_nexthop(&mut self, nexthop: &NexthopObject) -> io::Result<()> {
+        self.request(
+            nexthop_message(nexthop),
+            NLM_F_REQUEST | NLM_F_REPLACE | NLM_F_CREATE | NLM_F_ACK,
+        )?;
+        self.recv_ack()
+    }
+
+    fn delete_nexthop(&mut self, id: u32) -> io::Result<()> {
+        let nh_msg = NexthopMessage {
+            message_type: RTM_DELNEXTHOP,
+            attributes: vec![DefaultNla::new(NHA_ID, id.to_ne_bytes().to_vec())],
+            ..Default::default()
+        };
+
+        self.request(nh_msg, NLM_F_REQUEST | NLM_F_ACK)?;
+        self.recv_ack()
+    }
+
+    fn list_nexthops(&mut self) -> io::Result<Vec<NexthopObject>> {
+        let nh_msg = NexthopMessage {
+            message_type: RTM_GETNEXTHOP,
+            ..Default::default()
+        };
+
+        self.request(nh_msg, NLM_F_REQUEST | NLM_F_DUMP)?;
+
+        Ok(self
+            .recv_multipart::<NexthopMessage>()?
+            .iter()
+            .filter(|nh_msg| nh_msg.message_type == RTM_NEWNEXTHOP)
+            .map(parse_nexthop)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use netlink_packet_core::{NetlinkMessage, NetlinkPayload};
+
+    use super::*;
+
+    /// Serializes `nh_msg` with a netlink header and parses it back
+    fn round_trip(nh_msg: NexthopMessage) -> NexthopMessage {
+        let mut req = NetlinkMessage::new(
+            NetlinkHeader::default(),
+            NetlinkPayload::InnerMessage(nh_msg),
+        );
+        req.finalize();
+        let mut buf = vec![0u8; req.buffer_len()];
+        req.serialize(&mut buf);
+
+        match <NetlinkMessage<NexthopMessage>>::deserialize(&buf)
+            .unwrap()
+            .payload
+        {
+            NetlinkPayload::InnerMessage(nh_msg) => nh_msg,
+            payload => panic!("unexpected payload {payload:?}"),
+        }
+    }
+
+    fn attribute(nh_msg: &NexthopMessage, kind: u16) -> Vec<u8> {
+        nh_msg
+            .attributes
+            .iter()
+            .find(|nla| nla.kind() == kind)
+            .map(nla_value)
+            .unwrap()
+    }
+
+    #[test]
+    fn nhmsg_layout() {
+        let nexthop = NexthopObject::new(7)
+            .gateway("192.0.2.1".parse().unwrap())
+            .ifindex(3)
+            .onlink()
+            .protocol(42);
+        let nh_msg = nexthop_message(&nexthop);
+
+        let mut buf = vec![0u8; nh_msg.buffer_len()];
+        nh_msg.serialize(&mut buf);
+        // family, scope, protocol, resvd, flags
+        assert_eq!(buf[..4], [libc::AF_INET as u8, 0, 42, 0]);
+        assert_eq!(buf[4..8], (NextHopFlags::ONLINK.0 as u32).to_ne_bytes());
+        // NHA_ID comes first
+        assert_eq!(buf[8..12], [8, 0, NHA_ID as u8, 0]);
+        assert_eq!(buf[12..16], 7u32.to_ne_bytes());
+    }
+
+    #[test]
+    fn single_nexthop_round_trip() {
+        let nexthops = [
+            NexthopObject::new(1)
+                .gateway("192.0.2.1".parse().unwrap())
+                .ifindex(3)
+                .onlink(),
+            NexthopObject::new(2)
+                .gateway("2001:db8::1".parse().unwrap())
+                .ifindex(4)
+                .ipv6(),
+            NexthopObject::new(3).ifindex(5).ipv6(),
+            NexthopObject::new(4).blackhole(),
+        ];
+
+        for nexthop in nexthops {
+            let nh_msg = round_trip(nexthop_message(&nexthop));
+            assert_eq!(
+                parse_nexthop(&nh_msg),
+                NexthopObject {
+                    protocol: Some(u8::from(RouteProtocol::Boot)),
+                    ..nexthop
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn group_layout() {
+        let nexthop = NexthopObject::new(10).member(1, 1).member(2, 256);
+        let nh_msg = nexthop_message(&nexthop);
+
+        assert_eq!(nh_msg.family, libc::AF_UNSPEC as u8);
+        // struct nexthop_grp: id, weight - 1, resvd1, resvd2
+        let mut group = 1u32.to_ne_bytes().to_vec();
+        group.extend_from_slice(&[0, 0, 0, 0]);
+        group.extend_from_slice(&2u32.to_ne_bytes());
+        group.extend_from_slice(&[255, 0, 0, 0]);
+        assert_eq!(attribute(&nh_msg, NHA_GROUP), group);
+        assert!(nh_msg
+            .attributes
+            .iter()
+            .all(|nla| nla.kind() != NHA_GROUP_TYPE));
+    }
+
+    #[test]
+    fn group_weight_is_clamped() {
+        let nexthop = NexthopObject::new(10).member(1, 0).member(2, 300);
+        let group = attribute(&nexthop_message(&nexthop), NHA_GROUP);
+
+        assert_eq!(group[4], 0);
+        assert_eq!(group[12], 255);
+    }
+
+    #[test]
+    fn group_round_trip() {
+        let nexthop = NexthopObject::new(10)
+            .member(1, 1)
+            .member(2, 3)
+            .member(3, 256)
+            .protocol(99);
+
+        let nh_msg = round_trip(nexthop_message(&nexthop));
+        assert_eq!(parse_nexthop(&nh_msg), nexthop);
+    }
+
+    #[test]
+    fn resilient_group_layout() {
+        let nexthop = NexthopObject::new(11)
+            .member(1, 1)
+            .member(2, 1)
+            .resilient(ResilientGroup {
+                buckets: Some(64),
+                idle_timer: None,
+                unbalanced_timer: None,
+            });
+        let nh_msg = nexthop_message(&nexthop);
+
+        assert_eq!(
+            attribute(&nh_msg, NHA_GROUP_TYPE),
+            NEXTHOP_GRP_TYPE_RES.to_ne_bytes()
+        );
+        let res_group = nh_msg
+            .attributes
+            .iter()
+            .find(|nla| nla.kind() == NHA_RES_GROUP | NLA_F_NESTED)
+            .map(nla_value)
+            .unwrap();
+        // a single NHA_RES_GROUP_BUCKETS, padded to 4 bytes
+        let mut buckets = vec![6, 0, NHA_RES_GROUP_BUCKETS as u8, 0];
+        buckets.extend_from_slice(&64u16.to_ne_bytes());
+        buckets.extend_from_slice(&[0, 0]);
+        assert_eq!(res_group, buckets);
+    }
+
+    #[test]
+    fn resilient_group_round_trip() {
+        let groups = [
+            ResilientGroup {
+                buckets: Some(128),
+                idle_timer: Some(120),
+                unbalanced_timer: Some(30),
+            },
+            ResilientGroup::default(),
+        ];
+
+        for resilient in groups {
+            let nexthop = NexthopObject::new(12)
+                .member(1, 2)
+                .member(2, 1)
+                .resilient(resilient);
+
+            let nh_msg = round_trip(nexthop_message(&nexthop));
+            assert_eq!(
+                parse_nexthop(&nh_msg),
+                NexthopObject {
+                    protocol: Some(u8::from(RouteProtocol::Boot)),
+                    ..nexthop
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn short_nhmsg_is_an_error() {
+        let header = NetlinkHeader::default();
+        assert!(NexthopMessage::deserialize(&header, &[0; 4]).is_err());
+    }
+}
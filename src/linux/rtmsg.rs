@@ -1,162 +1,691 @@
-// use std::{net::IpAddr, slice};
-
-// use libc::{c_void, nlmsghdr, RTA_DST, RTA_GATEWAY, RTA_OIF};
-
-
-// const NLMSG_ALIGNTO: usize = 4;
-
-// #[macro_export]
-// macro_rules! NLMSG_ALIGN {
-//     ($len: expr) => {
-//         (($len + NLMSG_ALIGNTO - 1) & !(NLMSG_ALIGNTO - 1))
-//     };
-// }
-
-// const NLMSG_HDRLEN: usize = NLMSG_ALIGN!(std::mem::size_of::<nlmsghdr>());
-
-// #[macro_export]
-// macro_rules! NLMSG_LENGTH {
-//     ($len: expr) => {
-//         ($len + NLMSG_HDRLEN)
-//     };
-// }
-
-// #[macro_export]
-// macro_rules! NLMSG_TAIL {
-//     ($nmsg: expr) => {
-//         {
-//             let rtattr = unsafe {
-//                 ($nmsg as *const nlmsghdr as *const c_void)
-//                     .add(NLMSG_ALIGN!($nmsg.nlmsg_len as usize))
-//             };
-
-//             unsafe {
-//                 &mut *(rtattr as *mut rtattr)
-//             }
-//         }
-//     }
-// }
-
-// const RTA_ALIGNTO: usize = 4;
-
-// #[macro_export]
-// macro_rules! RTA_ALIGN {
-//     ($len: expr) => {
-//         (($len + RTA_ALIGNTO - 1) & !(RTA_ALIGNTO - 1))
-//     };
-// }
-
-// #[macro_export]
-// macro_rules! RTA_LENGTH {
-//     ($len: expr) => {
-//         RTA_ALIGN!(std::mem::size_of::<rtattr>()) + $len
-//     };
-// }
-
-// // #define RTA_DATA(rta)   ((void*)(((char*)(rta)) + RTA_LENGTH(0)))
-// #[macro_export]
-// macro_rules! RTA_DATA {
-//     ($rta: expr) => {
-//         {
-//             let data = unsafe {
-//                 ($rta as *const rtattr as *const c_void).add(RTA_LENGTH!(0))
-//             };
-
-//             unsafe {
-//                 &mut *(data as *mut u8)
-//             }
-//         }
-//     }
-// }
-
-
-// #[repr(C)]
-// #[allow(non_camel_case_types)]
-// pub(super) struct rtmsg {
-//     pub rtm_family: u8,
-//     pub rtm_dst_len: u8,
-//     pub rtm_src_len: u8,
-//     pub rtm_tos: u8,
-
-//     pub rtm_table: u8,      /* Routing table id */
-//     pub rtm_protocol: u8,   /* Routing protocol */
-//     pub rtm_scope: u8,   
-//     pub rtm_type: u8,   
-
-//     pub rtm_flags: u32,
-// }
-
-// #[repr(C)]
-// #[allow(non_camel_case_types)]
-// pub(super) struct rtattr {
-//     rta_len: u16,
-//     rta_type: u16,
-// }
-
-// #[repr(C)]
-// #[allow(non_camel_case_types)]
-// pub(super) struct m_rtmsg {
-//     pub n: nlmsghdr,
-//     pub r: rtmsg,
-//     pub attr: [u8; 4096],
-// }
-
-// impl Default for m_rtmsg {
-//     fn default() -> Self {
-//         let mut rtmsg = unsafe {
-//             std::mem::zeroed::<m_rtmsg>()
-//         };
-
-//         rtmsg.n.nlmsg_len = NLMSG_LENGTH!(std::mem::size_of::<rtmsg>()) as u32;
-
-//         rtmsg
-//     }
-// }
-
-// impl m_rtmsg {
-//     fn add_attr(&mut self, ty: u16, data: &[u8]) {
-//         let rta = NLMSG_TAIL!(&self.n);
-//         rta.rta_type = ty;
-
-//         let len = RTA_LENGTH!(data.len());
-
-//         if NLMSG_ALIGN!(self.n.nlmsg_len as usize) + RTA_ALIGN!(len) > std::mem::size_of::<m_rtmsg>() {
-//             return;
-//         }
-
-//         rta.rta_len = len as u16;
-//         let p = RTA_DATA!(rta);
-//         let slice = unsafe {
-//             slice::from_raw_parts_mut(p, data.len())
-//         };
-//         slice.copy_from_slice(data);
-
-//         self.n.nlmsg_len = (NLMSG_ALIGN!(self.n.nlmsg_len as usize) + RTA_ALIGN!(len)) as u32;
-//     }
-
-//     pub fn put_destination(&mut self, dst: &IpAddr) {
-//         match dst {
-//             IpAddr::V4(addr) => {
-//                 self.add_attr(RTA_DST, &addr.octets())
-//             },
-//             IpAddr::V6(addr) => {
-//                 self.add_attr(RTA_DST, &addr.octets())
-//             },
-//         }
-//     }
-
-//     pub fn put_index(&mut self, ifindex: u32) {
-//         self.add_attr(RTA_OIF, &ifindex.to_ne_bytes())
-//     }
-
-//     pub fn put_gateway(&mut self, gateway: &IpAddr) {
-//         match gateway {
-//             IpAddr::V4(addr) => {
-//                 self.add_attr(RTA_GATEWAY, &addr.octets())
-//             },
-//             IpAddr::V6(addr) => {
-//                 self.add_attr(RTA_GATEWAY, &addr.octets())
-//             },
-//         }
-//     }
-// }
\ No newline at end of file
+use std::{
+    io,
+    net::{IpAddr, Ipv6Addr},
+};
+
+use netlink_packet_route::{
+    route::{
+        MplsLabel, RouteAddress, RouteAttribute, RouteFlag, RouteHeader, RouteMessage, RouteMetric,
+        RouteNextHop, RouteNextHopFlag, RouteRealm, RouteVia,
+    },
+    AddressFamily,
+};
+use netlink_packet_utils::nla::{DefaultNla, Nla};
+
+use super::lwtunnel::parse_encap;
+use crate::{NextHop, NextHopFlags, Route, RouteKind, RouteMetrics, RouteScope};
+
+pub(super) const RT_TABLE_MAIN: u32 = RouteHeader::RT_TABLE_MAIN as u32;
+
+const RTM_NEWROUTE: u16 = 24;
+const RTM_DELROUTE: u16 = 25;
+const RTA_METRICS: u16 = 8;
+const RTAX_CC_ALGO: u16 = 16;
+/// `RTAX_CC_ALGO` carrying the algorithm name, see `fixup_metrics`
+const RTAX_CC_ALGO_NAME: u16 = 0x100 | RTAX_CC_ALGO;
+const NLA_TYPE_MASK: u16 = 0x3fff;
+const RTA_NH_ID: u16 = 30;
+
+/// Clock ticks per second (`USER_HZ`), the unit of kernel timers
+pub(super) fn user_hz() -> u32 {
+    unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as u32
+}
+
+pub(super) fn family(addr: &IpAddr) -> AddressFamily {
+    match addr {
+        IpAddr::V4(_) => AddressFamily::Inet,
+        IpAddr::V6(_) => AddressFamily::Inet6,
+    }
+}
+
+pub(super) fn route_address(addr: &IpAddr) -> RouteAddress {
+    match addr {
+        IpAddr::V4(addr) => RouteAddress::Inet(*addr),
+        IpAddr::V6(addr) => RouteAddress::Inet6(*addr),
+    }
+}
+
+pub(super) fn ip_address(addr: &RouteAddress) -> Option<IpAddr> {
+    match addr {
+        RouteAddress::Inet(addr) => Some(IpAddr::V4(*addr)),
+        RouteAddress::Inet6(addr) => Some(IpAddr::V6(*addr)),
+        _ => None,
+    }
+}
+
+/// `RTA_GATEWAY` if `gateway` has the route `family`, otherwise `RTA_VIA`,
+/// e.g. for an IPv4 route via an IPv6 next hop
+pub(super) fn gateway_attribute(family: AddressFamily, gateway: &IpAddr) -> RouteAttribute {
+    if self::family(gateway) == family {
+        return RouteAttribute::Gateway(route_address(gateway));
+    }
+
+    RouteAttribute::Via(match gateway {
+        IpAddr::V4(addr) => RouteVia::Inet(*addr),
+        IpAddr::V6(addr) => RouteVia::Inet6(*addr),
+    })
+}
+
+fn via_address(via: &RouteVia) -> Option<IpAddr> {
+    match via {
+        RouteVia::Inet(addr) => Some(IpAddr::V4(*addr)),
+        RouteVia::Inet6(addr) => Some(IpAddr::V6(*addr)),
+        _ => None,
+    }
+}
+
+/// `rtm_table` is only 8 bits wide, ids above 255 go to `RTA_TABLE`
+pub(super) fn set_table(rt_msg: &mut RouteMessage, table: u32) {
+    if table > 255 {
+        rt_msg.header.table = RouteHeader::RT_TABLE_UNSPEC;
+        rt_msg.attributes.push(RouteAttribute::Table(table));
+    } else {
+        rt_msg.header.table = table as u8;
+    }
+}
+
+/// Builds the part of a route message which identifies `route`:
+/// family, destination, prefix, source, table, tos, metric, preferred
+/// source, nexthop id and realms.
+pub(super) fn route_message(route: &Route) -> RouteMessage {
+    let mut rt_msg = RouteMessage::default();
+
+    rt_msg.header.address_family = family(&route.destination);
+    rt_msg.header.destination_prefix_length = route.prefix;
+    rt_msg
+        .attributes
+        .push(RouteAttribute::Destination(route_address(
+            &route.destination,
+        )));
+
+    if let Some((source, prefix)) = route.source {
+        rt_msg.header.source_prefix_length = prefix;
+        rt_msg
+            .attributes
+            .push(RouteAttribute::Source(route_address(&source)));
+    }
+
+    set_table(&mut rt_msg, route.table.unwrap_or(RT_TABLE_MAIN));
+    rt_msg.header.tos = route.tos.unwrap_or(0);
+
+    if let Some(metric) = route.metric {
+        rt_msg.attributes.push(RouteAttribute::Priority(metric));
+    }
+
+    if let Some(pref_source) = route.pref_source {
+        rt_msg
+            .attributes
+            .push(RouteAttribute::PrefSource(route_address(&pref_source)));
+    }
+
+    if let Some(id) = route.nexthop_id {
+        rt_msg
+            .attributes
+            .push(RouteAttribute::Other(DefaultNla::new(
+                RTA_NH_ID,
+                id.to_ne_bytes().to_vec(),
+            )));
+    }
+
+    if let Some((from, to)) = route.realm {
+        rt_msg.attributes.push(RouteAttribute::Realm(RouteRealm {
+            source: from,
+            destination: to,
+        }));
+    }
+
+    rt_msg
+}
+
+/// `RTM_DELROUTE` body selecting exactly `route`: besides the fields of
+/// `route_message`, the kernel matches gateway, interface, protocol, next
+/// hops and the route type, unless it is the default `Unicast`.
+pub(super) fn delete_message(route: &Route) -> RouteMessage {
+    let mut rt_msg = route_message(route);
+    rt_msg.header.scope = u8::from(route.scope.unwrap_or(RouteScope::NoWhere)).into();
+    if let Some(protocol) = route.protocol {
+        rt_msg.header.protocol = protocol.into();
+    }
+    if route.kind != RouteKind::Unicast {
+        rt_msg.header.kind = u8::from(route.kind).into();
+    }
+
+    if let Some(gateway) = route.gateway {
+        rt_msg
+            .attributes
+            .push(gateway_attribute(rt_msg.header.address_family, &gateway));
+    }
+    if let Some(index) = route.ifindex {
+        rt_msg.attributes.push(RouteAttribute::Oif(index));
+    }
+    push_next_hops(&mut rt_msg, &route.next_hops);
+
+    rt_msg
+}
+
+/// Only `ONLINK` and `PERVASIVE` are encoded, the other flags are reported
+/// by the kernel and IPv4 rejects some of them, e.g. `LINKDOWN` on a route
+/// read back while its link is down
+fn next_hop_flags(flags: NextHopFlags) -> Vec<RouteNextHopFlag> {
+    let mut ret = vec![];
+    if flags.contains(NextHopFlags::ONLINK) {
+        ret.push(RouteNextHopFlag::Onlink);
+    }
+    if flags.contains(NextHopFlags::PERVASIVE) {
+        ret.push(RouteNextHopFlag::Pervasive);
+    }
+
+    ret
+}
+
+fn parse_next_hop_flags(flags: &[RouteNextHopFlag]) -> NextHopFlags {
+    NextHopFlags(flags.iter().fold(0, |acc, flag| acc | u8::from(*flag)))
+}
+
+/// `rtm_flags` shares its low byte with the next hop flags, see `next_hop_flags`
+pub(super) fn route_flags(flags: NextHopFlags) -> Vec<RouteFlag> {
+    let mut ret = vec![];
+    if flags.contains(NextHopFlags::ONLINK) {
+        ret.push(RouteFlag::Onlink);
+    }
+    if flags.contains(NextHopFlags::PERVASIVE) {
+        ret.push(RouteFlag::Pervasive);
+    }
+
+    ret
+}
+
+fn parse_route_flags(flags: &[RouteFlag]) -> NextHopFlags {
+    let flags = flags.iter().fold(0, |acc, flag| acc | u32::from(*flag));
+
+    NextHopFlags(flags as u8)
+}
+
+/// The scope `ip route add` would pick for `route`
+pub(super) fn auto_scope(route: &Route) -> RouteScope {
+    match route.kind {
+        RouteKind::Local | RouteKind::Nat => RouteScope::Host,
+        RouteKind::Broadcast | RouteKind::Multicast | RouteKind::Anycast => RouteScope::Link,
+        // like iproute2, any gateway, multipath or nexthop object keeps universe
+        RouteKind::Unicast
+            if route.gateway.is_none()
+                && route.next_hops.is_empty()
+                && route.nexthop_id.is_none() =>
+        {
+            RouteScope::Link
+        }
+        _ => RouteScope::Universe,
+    }
+}
+
+/// Encodes the IPv6 preference and lifetime of `route`. IPv4 routes
+/// with a lifetime are refused, the IPv4 FIB would install them for good.
+pub(super) fn push_expiry(rt_msg: &mut RouteMessage, route: &Route) -> io::Result<()> {
+    if let Some(preference) = route.preference {
+        rt_msg
+            .attributes
+            .push(RouteAttribute::Preference(u8::from(preference).into()));
+    }
+
+    if let Some(expires) = route.expires {
+        if route.destination.is_ipv4() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "route expiry is only supported for IPv6",
+            ));
+        }
+        rt_msg.attributes.push(RouteAttribute::Expires(expires));
+    }
+
+    Ok(())
+}
+
+pub(super) fn mpls_labels(labels: &[u32]) -> Vec<MplsLabel> {
+    labels
+        .iter()
+        .enumerate()
+        .map(|(i, label)| MplsLabel {
+            label: *label,
+            traffic_class: 0,
+            bottom_of_stack: i == labels.len() - 1,
+            ttl: 0,
+        })
+        .collect()
+}
+
+/// Encodes `next_hops` as `RTA_MULTIPATH`, nothing for a single path route
+pub(super) fn push_next_hops(rt_msg: &mut RouteMessage, next_hops: &[NextHop]) {
+    if next_hops.is_empty() {
+        return;
+    }
+
+    let family = rt_msg.header.address_family;
+    let next_hops = next_hops
+        .iter()
+        .map(|next_hop| {
+            let mut nh = RouteNextHop::default();
+            nh.flags = next_hop_flags(next_hop.flags);
+            // rtnh_hops holds the weight minus one
+            nh.hops = (next_hop.weight.clamp(1, 256) - 1) as u8;
+            nh.interface_index = next_hop.ifindex.unwrap_or(0);
+            if let Some(gateway) = next_hop.gateway {
+                nh.attributes.push(gateway_attribute(family, &gateway));
+            }
+            nh
+        })
+        .collect();
+
+    rt_msg.attributes.push(RouteAttribute::MultiPath(next_hops));
+}
+
+/// Encodes `metrics` as `RTA_METRICS`, nothing if none is set
+pub(super) fn push_metrics(rt_msg: &mut RouteMessage, metrics: &RouteMetrics) {
+    if metrics.is_empty() {
+        return;
+    }
+
+    let mut nlas = vec![];
+    if metrics.lock != 0 {
+        nlas.push(RouteMetric::Lock(metrics.lock));
+    }
+    if let Some(mtu) = metrics.mtu {
+        nlas.push(RouteMetric::Mtu(mtu));
+    }
+    if let Some(advmss) = metrics.advmss {
+        nlas.push(RouteMetric::Advmss(advmss));
+    }
+    if let Some(window) = metrics.window {
+        nlas.push(RouteMetric::Window(window));
+    }
+    if let Some(rtt) = metrics.rtt {
+        nlas.push(RouteMetric::Rtt(rtt));
+    }
+    if let Some(initcwnd) = metrics.initcwnd {
+        nlas.push(RouteMetric::InitCwnd(initcwnd));
+    }
+    if let Some(initrwnd) = metrics.initrwnd {
+        nlas.push(RouteMetric::InitRwnd(initrwnd));
+    }
+    if let Some(hoplimit) = metrics.hoplimit {
+        nlas.push(RouteMetric::Hoplimit(hoplimit));
+    }
+    if let Some(quickack) = metrics.quickack {
+        nlas.push(RouteMetric::QuickAck(quickack as u32));
+    }
+    if let Some(congctl) = &metrics.congctl {
+        // the kernel wants a string here, not the u32 `RouteMetric::CcAlgo` emits
+        let mut name = congctl.as_bytes().to_vec();
+        name.push(0);
+        nlas.push(RouteMetric::Other(DefaultNla::new(RTAX_CC_ALGO, name)));
+    }
+
+    rt_msg.attributes.push(RouteAttribute::Metrics(nlas));
+}
+
+fn parse_metrics(nlas: &[RouteMetric]) -> RouteMetrics {
+    let mut metrics = RouteMetrics::default();
+
+    for nla in nlas {
+        match nla {
+            RouteMetric::Lock(lock) => metrics.lock = *lock,
+            RouteMetric::Mtu(mtu) => metrics.mtu = Some(*mtu),
+            RouteMetric::Advmss(advmss) => metrics.advmss = Some(*advmss),
+            RouteMetric::Window(window) => metrics.window = Some(*window),
+            RouteMetric::Rtt(rtt) => metrics.rtt = Some(*rtt),
+            RouteMetric::InitCwnd(initcwnd) => metrics.initcwnd = Some(*initcwnd),
+            RouteMetric::InitRwnd(initrwnd) => metrics.initrwnd = Some(*initrwnd),
+            RouteMetric::Hoplimit(hoplimit) => metrics.hoplimit = Some(*hoplimit),
+            RouteMetric::QuickAck(quickack) => metrics.quickack = Some(*quickack != 0),
+            // a 3 letters name plus NUL fits in a u32
+            RouteMetric::CcAlgo(name) => metrics.congctl = Some(cc_algo_name(&name.to_ne_bytes())),
+            RouteMetric::Other(nla) if nla.kind() == RTAX_CC_ALGO_NAME => {
+                let mut name = vec![0u8; nla.value_len()];
+                nla.emit_value(&mut name);
+                metrics.congctl = Some(cc_algo_name(&name));
+            }
+            _ => (),
+        }
+    }
+
+    metrics
+}
+
+fn cc_algo_name(name: &[u8]) -> String {
+    let len = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+    String::from_utf8_lossy(&name[..len]).into_owned()
+}
+
+/// `RTAX_CC_ALGO` is a string, but netlink-packet-route parses it as u32
+/// and rejects the whole route message unless the name is 3 letters long.
+/// Retag those attributes in the raw messages so they are parsed as `Other`.
+pub(super) fn fixup_metrics(buf: &mut [u8]) {
+    let mut offset = 0;
+
+    while offset + 16 <= buf.len() {
+        let len = u32::from_ne_bytes(buf[offset..offset + 4].try_into().unwrap()) as usize;
+        let ty = u16::from_ne_bytes([buf[offset + 4], buf[offset + 5]]);
+        if len < 16 || offset + len > buf.len() {
+            return;
+        }
+
+        if ty == RTM_NEWROUTE || ty == RTM_DELROUTE {
+            // nlmsghdr + rtmsg
+            for (start, end) in nla_ranges(buf, offset + 16 + 12, offset + len) {
+                if nla_kind(buf, start) != RTA_METRICS {
+                    continue;
+                }
+                for (start, nla_end) in nla_ranges(buf, start + 4, end) {
+                    if nla_kind(buf, start) == RTAX_CC_ALGO && nla_end - start != 8 {
+                        buf[start + 2..start + 4].copy_from_slice(&RTAX_CC_ALGO_NAME.to_ne_bytes());
+                    }
+                }
+            }
+        }
+
+        offset += (len + 3) & !3;
+    }
+}
+
+fn nla_kind(buf: &[u8], start: usize) -> u16 {
+    u16::from_ne_bytes([buf[start + 2], buf[start + 3]]) & NLA_TYPE_MASK
+}
+
+/// `(start, end)` of every attribute between `offset` and `end`
+fn nla_ranges(buf: &[u8], mut offset: usize, end: usize) -> Vec<(usize, usize)> {
+    let mut ranges = vec![];
+
+    while offset + 4 <= end {
+        let len = u16::from_ne_bytes([buf[offset], buf[offset + 1]]) as usize;
+        if len < 4 || offset + len > end {
+            break;
+        }
+        ranges.push((offset, offset + len));
+        offset += (len + 3) & !3;
+    }
+
+    ranges
+}
+
+fn parse_next_hop(nh: &RouteNextHop) -> NextHop {
+    let mut next_hop = NextHop {
+        ifindex: Some(nh.interface_index).filter(|index| *index != 0),
+        weight: nh.hops as u16 + 1,
+        flags: parse_next_hop_flags(&nh.flags),
+        ..Default::default()
+    };
+
+    for attr in &nh.attributes {
+        match attr {
+            RouteAttribute::Gateway(addr) => next_hop.gateway = ip_address(addr),
+            RouteAttribute::Via(via) => next_hop.gateway = via_address(via),
+            _ => (),
+        }
+    }
+
+    next_hop
+}
+
+pub(super) fn parse_route(rt_msg: &RouteMessage) -> Route {
+    let mut route = Route {
+        prefix: rt_msg.header.destination_prefix_length,
+        table: Some(rt_msg.header.table as u32),
+        kind: u8::from(rt_msg.header.kind).into(),
+        protocol: Some(u8::from(rt_msg.header.protocol)),
+        scope: Some(u8::from(rt_msg.header.scope).into()),
+        flags: parse_route_flags(&rt_msg.header.flags),
+        tos: Some(rt_msg.header.tos).filter(|tos| *tos != 0),
+        ..Default::default()
+    };
+    if rt_msg.header.address_family == AddressFamily::Inet6 {
+        route.destination = IpAddr::V6(Ipv6Addr::UNSPECIFIED);
+    }
+
+    let encap_type = rt_msg
+        .attributes
+        .iter()
+        .find_map(|attr| match attr {
+            RouteAttribute::EncapType(kind) => Some(*kind),
+            _ => None,
+        })
+        .unwrap_or_default();
+
+    for attr in &rt_msg.attributes {
+        match attr {
+            RouteAttribute::Destination(addr) => {
+                if let Some(addr) = ip_address(addr) {
+                    route.destination = addr;
+                }
+            }
+            RouteAttribute::Gateway(addr) => {
+                route.gateway = ip_address(addr);
+            }
+            RouteAttribute::Via(via) => {
+                route.gateway = via_address(via);
+            }
+            RouteAttribute::Realm(realm) => {
+                route.realm = Some((realm.source, realm.destination));
+            }
+            RouteAttribute::Oif(ifindex) => {
+                route.ifindex = Some(*ifindex);
+            }
+            RouteAttribute::Table(table) => {
+                route.table = Some(*table);
+            }
+            RouteAttribute::Priority(metric) => {
+                route.metric = Some(*metric);
+            }
+            RouteAttribute::PrefSource(addr) => {
+                route.pref_source = ip_address(addr);
+            }
+            RouteAttribute::Source(addr) => {
+                route.source =
+                    ip_address(addr).map(|addr| (addr, rt_msg.header.source_prefix_length));
+            }
+            RouteAttribute::MultiPath(next_hops) => {
+                route.next_hops = next_hops.iter().map(parse_next_hop).collect();
+            }
+            RouteAttribute::Metrics(nlas) => {
+                route.metrics = parse_metrics(nlas);
+            }
+            RouteAttribute::Encap(nlas) => {
+                route.encap = parse_encap(encap_type, nlas);
+            }
+            RouteAttribute::Preference(preference) => {
+                route.preference = Some(u8::from(*preference).into());
+            }
+            RouteAttribute::CacheInfo(cache_info) if cache_info.expires != 0 => {
+                // rta_expires is in clock ticks
+                route.expires = Some(cache_info.expires / user_hz());
+            }
+            RouteAttribute::Other(nla) if nla.kind() == RTA_NH_ID && nla.value_len() == 4 => {
+                let mut id = [0u8; 4];
+                nla.emit_value(&mut id);
+                route.nexthop_id = Some(u32::from_ne_bytes(id));
+            }
+            _ => (),
+        }
+    }
+
+    route
+}
+
+#[cfg(test)]
+mod tests {
+    use netlink_packet_core::{NetlinkHeader, NetlinkMessage, NetlinkPayload};
+    use netlink_packet_route::RouteNetlinkMessage;
+
+    use super::*;
+
+    const NLMSG_HDRLEN: usize = 16;
+    const RTMSG_LEN: usize = 12;
+
+    fn nla(kind: u16, value: &[u8]) -> Vec<u8> {
+        let mut buf = ((4 + value.len()) as u16).to_ne_bytes().to_vec();
+        buf.extend_from_slice(&kind.to_ne_bytes());
+        buf.extend_from_slice(value);
+        buf.resize((buf.len() + 3) & !3, 0);
+        buf
+    }
+
+    /// `RTM_NEWROUTE` for 10.0.0.0/8 in the main table with `metrics` as
+    /// the payload of its `RTA_METRICS`
+    fn new_route(metrics: &[u8]) -> Vec<u8> {
+        let mut attrs = nla(1 /* RTA_DST */, &[10, 0, 0, 0]);
+        attrs.extend(nla(RTA_METRICS, metrics));
+
+        let len = NLMSG_HDRLEN + RTMSG_LEN + attrs.len();
+        let mut buf = (len as u32).to_ne_bytes().to_vec();
+        buf.extend_from_slice(&RTM_NEWROUTE.to_ne_bytes());
+        buf.extend_from_slice(&[0; 10]);
+        // family, dst_len, src_len, tos, table, protocol, scope, type, flags
+        buf.extend_from_slice(&[libc::AF_INET as u8, 8, 0, 0, 254, 4, 0, 1, 0, 0, 0, 0]);
+        buf.extend(attrs);
+        buf
+    }
+
+    fn parse(buf: &[u8]) -> Route {
+        let nlmsg = <NetlinkMessage<RouteNetlinkMessage>>::deserialize(buf).unwrap();
+        match nlmsg.payload {
+            NetlinkPayload::InnerMessage(RouteNetlinkMessage::NewRoute(rt_msg)) => {
+                parse_route(&rt_msg)
+            }
+            payload => panic!("unexpected payload {payload:?}"),
+        }
+    }
+
+    #[test]
+    fn three_letters_cc_algo_is_left_alone() {
+        let mut buf = new_route(&nla(RTAX_CC_ALGO, b"bbr\0"));
+        let orig = buf.clone();
+
+        fixup_metrics(&mut buf);
+        assert_eq!(buf, orig);
+        assert_eq!(parse(&buf).metrics.congctl.as_deref(), Some("bbr"));
+    }
+
+    #[test]
+    fn longer_cc_algo_is_retagged() {
+        let mut metrics = nla(2 /* RTAX_MTU */, &1400u32.to_ne_bytes());
+        metrics.extend(nla(RTAX_CC_ALGO, b"cubic\0"));
+        let mut buf = new_route(&metrics);
+
+        // netlink-packet-route refuses the message as is
+        assert!(<NetlinkMessage<RouteNetlinkMessage>>::deserialize(&buf).is_err());
+
+        fixup_metrics(&mut buf);
+        let route = parse(&buf);
+        assert_eq!(route.metrics.mtu, Some(1400));
+        assert_eq!(route.metrics.congctl.as_deref(), Some("cubic"));
+    }
+
+    #[test]
+    fn every_message_of_a_buffer_is_fixed() {
+        let mut buf = new_route(&nla(RTAX_CC_ALGO, b"reno\0"));
+        let first = buf.len();
+        buf.extend(new_route(&nla(RTAX_CC_ALGO, b"westwood\0")));
+
+        fixup_metrics(&mut buf);
+        assert_eq!(
+            parse(&buf[..first]).metrics.congctl.as_deref(),
+            Some("reno")
+        );
+        assert_eq!(
+            parse(&buf[first..]).metrics.congctl.as_deref(),
+            Some("westwood")
+        );
+    }
+
+    #[test]
+    fn truncated_messages_are_left_alone() {
+        let buf = new_route(&nla(RTAX_CC_ALGO, b"cubic\0"));
+
+        for len in 0..buf.len() {
+            let mut truncated = buf[..len].to_vec();
+            fixup_metrics(&mut truncated);
+            assert_eq!(truncated, buf[..len]);
+        }
+    }
+
+    #[test]
+    fn malformed_attributes_are_left_alone() {
+        // the nested attribute claims more bytes than RTA_METRICS holds
+        let mut metrics = nla(RTAX_CC_ALGO, b"cubic\0");
+        metrics[..2].copy_from_slice(&64u16.to_ne_bytes());
+        let mut buf = new_route(&metrics);
+        let orig = buf.clone();
+        fixup_metrics(&mut buf);
+        assert_eq!(buf, orig);
+
+        // attribute lengths below the attribute header
+        let mut metrics = nla(RTAX_CC_ALGO, b"cubic\0");
+        metrics[..2].copy_from_slice(&2u16.to_ne_bytes());
+        let mut buf = new_route(&metrics);
+        let orig = buf.clone();
+        fixup_metrics(&mut buf);
+        assert_eq!(buf, orig);
+
+        // a message shorter than nlmsghdr + rtmsg
+        let mut buf = new_route(&nla(RTAX_CC_ALGO, b"cubic\0"));
+        buf[..4].copy_from_slice(&20u32.to_ne_bytes());
+        let orig = buf.clone();
+        fixup_metrics(&mut buf);
+        assert_eq!(buf, orig);
+    }
+
+    #[test]
+    fn metrics_round_trip() {
+        let metrics = RouteMetrics {
+            mtu: Some(1400),
+            advmss: Some(1360),
+            window: Some(65535),
+            rtt: Some(100),
+            initcwnd: Some(10),
+            initrwnd: Some(20),
+            hoplimit: Some(32),
+            quickack: Some(true),
+            congctl: Some("cubic".to_string()),
+            lock: RouteMetrics::LOCK_MTU | RouteMetrics::LOCK_CONGCTL,
+        };
+        let mut rt_msg = route_message(&Route::new("10.0.0.0".parse().unwrap(), 8));
+        push_metrics(&mut rt_msg, &metrics);
+
+        let mut req = NetlinkMessage::new(
+            NetlinkHeader::default(),
+            NetlinkPayload::from(RouteNetlinkMessage::NewRoute(rt_msg)),
+        );
+        req.finalize();
+        let mut buf = vec![0u8; req.buffer_len()];
+        req.serialize(&mut buf);
+
+        fixup_metrics(&mut buf);
+        assert_eq!(parse(&buf).metrics, metrics);
+    }
+
+    #[test]
+    fn expiry_is_ipv6_only() {
+        let route = Route::new("2001:db8::".parse().unwrap(), 32).expires(60);
+        let mut rt_msg = route_message(&route);
+        push_expiry(&mut rt_msg, &route).unwrap();
+        assert!(rt_msg.attributes.contains(&RouteAttribute::Expires(60)));
+
+        let route = Route::new("10.0.0.0".parse().unwrap(), 8).expires(60);
+        let err = push_expiry(&mut route_message(&route), &route).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn reported_flags_are_not_encoded() {
+        let reported = NextHopFlags::DEAD | NextHopFlags::LINKDOWN | NextHopFlags::OFFLOAD;
+        let flags = reported | NextHopFlags::ONLINK | NextHopFlags::PERVASIVE;
+
+        assert_eq!(
+            route_flags(flags),
+            [RouteFlag::Onlink, RouteFlag::Pervasive]
+        );
+        assert!(route_flags(reported).is_empty());
+        assert_eq!(
+            next_hop_flags(flags),
+            [RouteNextHopFlag::Onlink, RouteNextHopFlag::Pervasive]
+        );
+    }
+}
@@ -5,11 +5,15 @@ use std::{
     os::fd::{AsRawFd, RawFd}
 };
 
-use crate::{macos::rtmsg::m_rtmsg, syscall, Route, RouteAction, RouteChange};
+use crate::{
+    macos::rtmsg::m_rtmsg, syscall, IpFamily, Route, RouteAction, RouteChange, RouteFilter,
+    RouteKind,
+};
 use libc::{
-    rt_msghdr, AF_INET, AF_INET6, AF_ROUTE, AF_UNSPEC, RTAX_MAX, RTA_DST, 
-    RTA_GATEWAY, RTA_IFP, RTA_NETMASK, RTF_GATEWAY, RTF_STATIC, 
-    RTF_UP, RTM_ADD, RTM_DELETE, RTM_GET, RTM_VERSION, SOCK_RAW
+    rt_msghdr, AF_INET, AF_INET6, AF_ROUTE, AF_UNSPEC, CTL_NET, NET_RT_DUMP, PF_ROUTE, RTAX_MAX,
+    RTA_DST, RTA_GATEWAY, RTA_IFP, RTA_NETMASK, RTF_BLACKHOLE, RTF_BROADCAST, RTF_GATEWAY, RTF_HOST,
+    RTF_LOCAL, RTF_MULTICAST, RTF_REJECT, RTF_STATIC, RTF_UP, RTM_ADD, RTM_CHANGE, RTM_DELETE,
+    RTM_GET, RTM_VERSION, SOCK_RAW
 };
 
 
@@ -45,18 +49,20 @@ impl Read for RouteSock {
     }
 }
 
-impl RouteAction for RouteSock {
-    fn add(&mut self, route: &Route) -> io::Result<()> {
+impl RouteSock {
+    /// Sends `route` as `RTM_ADD` or `RTM_CHANGE`
+    fn write_route(&mut self, rtm_type: i32, route: &Route) -> io::Result<()> {
         let mut rtm_flags = (RTF_STATIC | RTF_UP) as i32;
 
         if route.gateway.is_some() {
             rtm_flags |= RTF_GATEWAY as i32;
         };
+        rtm_flags |= kind2flags(route.kind)?;
 
         let rtm_addrs = (RTA_DST | RTA_NETMASK | RTA_GATEWAY) as i32;
 
         let mut rtmsg: m_rtmsg = m_rtmsg::default();
-        rtmsg.hdr.rtm_type = RTM_ADD as u8;
+        rtmsg.hdr.rtm_type = rtm_type as u8;
         rtmsg.hdr.rtm_flags = rtm_flags;
         rtmsg.hdr.rtm_addrs = rtm_addrs;
         rtmsg.hdr.rtm_seq = 1;
@@ -89,7 +95,7 @@ impl RouteAction for RouteSock {
 
         let rt_hdr: &rt_msghdr = unsafe { std::mem::transmute(buf.as_ptr()) };
 
-        assert_eq!(rt_hdr.rtm_type, RTM_ADD as u8);
+        assert_eq!(rt_hdr.rtm_type, rtm_type as u8);
         assert_eq!(rt_hdr.rtm_version, RTM_VERSION as u8);
         if rt_hdr.rtm_errno != 0 {
             return Err(code2error(rt_hdr.rtm_errno));
@@ -97,10 +103,38 @@ impl RouteAction for RouteSock {
 
         Ok(())
     }
+}
+
+impl RouteAction for RouteSock {
+    fn add(&mut self, route: &Route) -> io::Result<()> {
+        self.write_route(RTM_ADD, route)
+    }
+
+    fn replace(&mut self, route: &Route) -> io::Result<()> {
+        match self.change(route) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => self.add(route),
+            ret => ret,
+        }
+    }
+
+    fn change(&mut self, route: &Route) -> io::Result<()> {
+        self.write_route(RTM_CHANGE, route)
+    }
+
+    fn append(&mut self, _route: &Route) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "routing socket can not append next hops",
+        ))
+    }
 
     fn delete(&mut self, route: &Route) -> io::Result<()> {
         let rtm_flags = (RTF_STATIC | RTF_UP | RTF_GATEWAY) as i32;
-        let rtm_addrs = (RTA_DST | RTA_NETMASK) as i32;
+        let mut rtm_addrs = (RTA_DST | RTA_NETMASK) as i32;
+        // with a gateway only the route through it is deleted
+        if route.gateway.is_some() {
+            rtm_addrs |= RTA_GATEWAY;
+        }
 
         let mut rtmsg = m_rtmsg::default();
         rtmsg.hdr.rtm_type = RTM_DELETE as u8;
@@ -109,6 +143,9 @@ impl RouteAction for RouteSock {
         rtmsg.hdr.rtm_seq = 1;
 
         rtmsg.put_destination(&route.destination);
+        if let Some(gateway) = route.gateway {
+            rtmsg.put_gateway(&gateway);
+        }
         rtmsg.put_netmask(&route.mask());
 
         rtmsg.hdr.rtm_msglen = rtmsg.len() as u16;
@@ -139,7 +176,6 @@ impl RouteAction for RouteSock {
     }
 
     fn get(&mut self, route: &Route) -> io::Result<Route> {
-        let mut ret = Route::default();
         let rtm_flags = (RTF_STATIC | RTF_UP | RTF_GATEWAY) as i32;
         let rtm_addrs = (RTA_DST | RTA_NETMASK | RTA_IFP) as i32;
         // if !route.destination.is_unspecified() {
@@ -183,46 +219,10 @@ impl RouteAction for RouteSock {
                 format!("message length mismatch, in packet {}, returned {}", rtmsg.hdr.rtm_msglen, n)
             ));
         }
-        rtmsg.attr_len = 0;
-
-        for offset in 0..RTAX_MAX {
-            if rtmsg.attr_len + std::mem::size_of::<rt_msghdr>() >= n {
-                break;
-            }
-            let bit = 1 << offset;
-            if rtmsg.hdr.rtm_addrs & bit == 0 {
-                continue;
-            }
-
-            match bit {
-                RTA_DST => {
-                    ret.destination = rtmsg.get_destination()
-                },
-                RTA_GATEWAY => ret.gateway = {
-                    let gateway = rtmsg.get_gateway();
-                    if rtmsg.hdr.rtm_flags & RTF_GATEWAY != 0 {
-                        Some(gateway)
-                    } else {
-                        None
-                    }
-                },
-                RTA_NETMASK => ret.cidr(
-                    rtmsg.get_netmask(if ret.destination.is_ipv4() {
-                        AF_INET as u8
-                    } else {
-                        AF_INET6 as u8
-                    })
-                ),
-                RTA_IFP => ret.ifindex = Some(rtmsg.get_index()),
-                _ => (),
-            }
-        }
-
-        Ok(ret)
+        Ok(parse_rtmsg(rtmsg, n))
     }
 
     fn monitor(&mut self, buf: &mut [u8]) -> io::Result<(crate::RouteChange, Route)> {
-        let mut ret = Route::default();
         let n = self.read(buf)?;
 
         let rtmsg: &mut m_rtmsg = unsafe { std::mem::transmute(buf.as_ptr()) };
@@ -233,46 +233,140 @@ impl RouteAction for RouteSock {
             ));
         }
         assert_eq!(rtmsg.hdr.rtm_version, RTM_VERSION as u8);
-        rtmsg.attr_len = 0;
-
         let rtm_type: RouteChange = rtmsg.hdr.rtm_type.into();
 
-        for offset in 0..RTAX_MAX {
-            if rtmsg.attr_len + std::mem::size_of::<rt_msghdr>() >= n {
+        Ok((rtm_type, parse_rtmsg(rtmsg, n)))
+    }
+
+    fn list(&mut self, filter: &RouteFilter) -> io::Result<Vec<Route>> {
+        if filter.table.is_some() || filter.protocol.is_some() || filter.scope.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "routing socket reports no table, protocol or scope",
+            ));
+        }
+
+        let family = match filter.family {
+            Some(IpFamily::V4) => AF_INET,
+            Some(IpFamily::V6) => AF_INET6,
+            None => AF_UNSPEC,
+        };
+        let mut mib = [CTL_NET, PF_ROUTE, 0, family, NET_RT_DUMP, 0];
+
+        // the table may grow between sizing and reading it, retry on ENOMEM
+        // with some slack like route(8) does
+        let mut retries = 0;
+        let (buf, len) = loop {
+            let mut len = 0;
+            syscall!(sysctl(
+                mib.as_mut_ptr(),
+                mib.len() as u32,
+                std::ptr::null_mut(),
+                &mut len,
+                std::ptr::null_mut(),
+                0
+            ))?;
+            len += len / 8;
+
+            let mut buf = vec![0u8; len];
+            match syscall!(sysctl(
+                mib.as_mut_ptr(),
+                mib.len() as u32,
+                buf.as_mut_ptr() as *mut _,
+                &mut len,
+                std::ptr::null_mut(),
+                0
+            )) {
+                Ok(_) => break (buf, len),
+                Err(e) if e.raw_os_error() == Some(libc::ENOMEM) && retries < 10 => retries += 1,
+                Err(e) => return Err(e),
+            }
+        };
+
+        let mut ret = vec![];
+        let mut offset = 0;
+        while offset + std::mem::size_of::<rt_msghdr>() <= len {
+            let mut rtmsg = m_rtmsg::default();
+            // rtm_msglen leads the header
+            let msglen = u16::from_ne_bytes([buf[offset], buf[offset + 1]]) as usize;
+            if msglen == 0 || offset + msglen > len {
                 break;
             }
-            let bit = 1 << offset;
-            if rtmsg.hdr.rtm_addrs & bit == 0 {
+
+            let n = msglen.min(std::mem::size_of::<rt_msghdr>() + rtmsg.attr.len());
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    buf[offset..].as_ptr(),
+                    &mut rtmsg as *mut m_rtmsg as *mut u8,
+                    n,
+                )
+            };
+            offset += msglen;
+
+            // skip entries without an IP destination, e.g. link layer ones
+            let dst_family = rtmsg.attr[1] as i32;
+            if rtmsg.hdr.rtm_addrs & RTA_DST == 0
+                || (dst_family != AF_INET && dst_family != AF_INET6)
+            {
                 continue;
             }
 
-            match bit {
-                RTA_DST => {
-                    ret.destination = rtmsg.get_destination()
-                },
-                RTA_GATEWAY => ret.gateway = {
-                    let gateway = rtmsg.get_gateway();
-                    if rtmsg.hdr.rtm_flags & RTF_GATEWAY != 0 {
-                        Some(gateway)
-                    } else {
-                        None
-                    }
-                },
-                RTA_NETMASK => ret.cidr(
-                    rtmsg.get_netmask(if ret.destination.is_ipv4() {
-                        AF_INET as u8
-                    } else {
-                        AF_INET6 as u8
-                    })
-                ),
-                RTA_IFP => ret.ifindex = Some(rtmsg.get_index()),
-                _ => (),
+            let route = parse_rtmsg(&mut rtmsg, n);
+            if filter.matches(&route) {
+                ret.push(route);
             }
         }
 
-        rtmsg.attr_len = 0;
-        Ok((rtm_type, ret))
+        Ok(ret)
+    }
+}
+
+/// Reads the route carried by the first `n` bytes of `rtmsg`
+fn parse_rtmsg(rtmsg: &mut m_rtmsg, n: usize) -> Route {
+    let mut ret = Route::default();
+    rtmsg.attr_len = 0;
+    ret.kind = flags2kind(rtmsg.hdr.rtm_flags);
+
+    for offset in 0..RTAX_MAX {
+        if rtmsg.attr_len + std::mem::size_of::<rt_msghdr>() >= n {
+            break;
+        }
+        let bit = 1 << offset;
+        if rtmsg.hdr.rtm_addrs & bit == 0 {
+            continue;
+        }
+
+        match bit {
+            RTA_DST => {
+                ret.destination = rtmsg.get_destination()
+            },
+            RTA_GATEWAY => ret.gateway = {
+                let gateway = rtmsg.get_gateway();
+                if rtmsg.hdr.rtm_flags & RTF_GATEWAY != 0 {
+                    Some(gateway)
+                } else {
+                    None
+                }
+            },
+            RTA_NETMASK => ret.cidr(
+                rtmsg.get_netmask(if ret.destination.is_ipv4() {
+                    AF_INET as u8
+                } else {
+                    AF_INET6 as u8
+                })
+            ),
+            RTA_IFP => ret.ifindex = Some(rtmsg.get_index()),
+            _ => (),
+        }
+    }
+
+    // host routes carry no netmask
+    if rtmsg.hdr.rtm_flags & RTF_HOST != 0 && rtmsg.hdr.rtm_addrs & RTA_NETMASK == 0 {
+        ret.prefix = if ret.destination.is_ipv4() { 32 } else { 128 };
     }
+
+    rtmsg.attr_len = 0;
+    ret
 }
 
 impl RouteSock {
@@ -295,6 +389,39 @@ impl Drop for RouteSock {
     }
 }
 
+/// Kinds without a routing socket flag are refused rather than
+/// installed as plain unicast routes
+fn kind2flags(kind: RouteKind) -> io::Result<i32> {
+    match kind {
+        RouteKind::Unicast => Ok(0),
+        RouteKind::Blackhole => Ok(RTF_BLACKHOLE),
+        RouteKind::Unreachable | RouteKind::Prohibit => Ok(RTF_REJECT),
+        RouteKind::Local => Ok(RTF_LOCAL),
+        RouteKind::Broadcast => Ok(RTF_BROADCAST),
+        RouteKind::Multicast => Ok(RTF_MULTICAST),
+        kind => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("routing socket can not express {kind:?} routes"),
+        )),
+    }
+}
+
+fn flags2kind(flags: i32) -> RouteKind {
+    if flags & RTF_BLACKHOLE != 0 {
+        RouteKind::Blackhole
+    } else if flags & RTF_REJECT != 0 {
+        RouteKind::Unreachable
+    } else if flags & RTF_LOCAL != 0 {
+        RouteKind::Local
+    } else if flags & RTF_BROADCAST != 0 {
+        RouteKind::Broadcast
+    } else if flags & RTF_MULTICAST != 0 {
+        RouteKind::Multicast
+    } else {
+        RouteKind::Unicast
+    }
+}
+
 fn code2error(err: i32) -> io::Error {
     let kind = match err {
         17 => io::ErrorKind::AlreadyExists, // EEXIST
@@ -124,12 +124,21 @@ impl m_rtmsg {
     }
 
     pub fn get_netmask(&mut self, family: u8) -> IpAddr {
-        let sa= unsafe {
-            &mut *(self.attr[self.attr_len..].as_ptr() as *mut sockaddr)
-        };
-        sa.sa_family = family;
+        // the kernel trims netmasks after their last non-zero byte,
+        // malformed ones may also run past the end of `attr`
+        let attr = self.attr.get(self.attr_len..).unwrap_or_default();
+        let sa_len = attr.first().map_or(0, |len| *len as u8 as usize);
+        let mut sa = [0u8; std::mem::size_of::<sockaddr_in6>()];
+        for (byte, attr) in sa.iter_mut().zip(attr).take(sa_len) {
+            *byte = *attr as u8;
+        }
+        self.attr_len += roundup!(sa_len);
 
-        self.get_addr()
+        if family == AF_INET as u8 {
+            IpAddr::from(<[u8; 4]>::try_from(&sa[4..8]).unwrap())
+        } else {
+            IpAddr::from(<[u8; 16]>::try_from(&sa[8..24]).unwrap())
+        }
     }
 
     pub fn get_index(&mut self) -> u32 {
@@ -0,0 +1,415 @@
+use std::{fmt, io};
+
+use crate::{IpFamily, NextHop, Route, RouteAction, RouteFilter, RouteKind, RouteOp, RouteScope};
+
+/// Linux main routing table (`RT_TABLE_MAIN`), where routes without a
+/// table go
+const RT_TABLE_MAIN: u32 = 254;
+
+/// Route changes made through a `RouteAction` which are undone together.
+///
+/// Each applied change is recorded with its inverse. When a change fails,
+/// or on `abort`, the recorded inverses are replayed in reverse order.
+/// A transaction dropped without `commit` is rolled back as well.
+pub struct RouteTransaction<'a, T: RouteAction> {
+    sock: &'a mut T,
+    undo: Vec<RouteOp>,
+}
+
+/// A failed transaction step, the transaction was rolled back.
+#[derive(Debug)]
+pub struct TransactionError {
+    /// Why the step failed.
+    pub error: io::Error,
+    /// Inverse operations which failed during the rollback, in the
+    /// order they were tried.
+    pub rollback_failures: Vec<(RouteOp, io::Error)>,
+}
+
+impl fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.error)?;
+        if !self.rollback_failures.is_empty() {
+            write!(
+                f,
+                ", {} change(s) could not be rolled back",
+                self.rollback_failures.len()
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for TransactionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl<'a, T: RouteAction> RouteTransaction<'a, T> {
+    pub fn new(sock: &'a mut T) -> RouteTransaction<'a, T> {
+        RouteTransaction { sock, undo: vec![] }
+    }
+
+    pub fn add(&mut self, route: &Route) -> Result<(), TransactionError> {
+        let ret = self.sock.add(route);
+        self.record(ret, RouteOp::Delete(route.clone()))
+    }
+
+    /// Deletes `route`, the rollback restores the route as the kernel
+    /// reported it before, or `route` itself if it could not be found.
+    /// Fails without deleting if the installed routes can not be read.
+    pub fn delete(&mut self, route: &Route) -> Result<(), TransactionError> {
+        let old = match self.find(route, deleted_by) {
+            Ok(old) => old.unwrap_or_else(|| route.clone()),
+            Err(error) => return Err(self.fail(error)),
+        };
+        let ret = self.sock.delete(route);
+        self.record(ret, RouteOp::Add(old))
+    }
+
+    /// Replaces `route`, the rollback puts back the replaced route or
+    /// deletes `route` if there was none. Fails without replacing if the
+    /// installed routes can not be read.
+    pub fn replace(&mut self, route: &Route) -> Result<(), TransactionError> {
+        let inverse = match self.find(route, replaced_by) {
+            Ok(Some(old)) => RouteOp::Replace(old),
+            Ok(None) => RouteOp::Delete(route.clone()),
+            Err(error) => return Err(self.fail(error)),
+        };
+        let ret = self.sock.replace(route);
+        self.record(ret, inverse)
+    }
+
+    /// Keeps every change made so far
+    pub fn commit(mut self) {
+        self.undo.clear();
+    }
+
+    /// Undoes every change made so far, returns the inverse operations
+    /// which failed
+    pub fn abort(mut self) -> Vec<(RouteOp, io::Error)> {
+        self.rollback()
+    }
+
+    fn record(&mut self, ret: io::Result<()>, inverse: RouteOp) -> Result<(), TransactionError> {
+        match ret {
+            Ok(()) => {
+                self.undo.push(inverse);
+                Ok(())
+            }
+            Err(error) => Err(self.fail(error)),
+        }
+    }
+
+    /// Rolls back after a step failed with `error`
+    fn fail(&mut self, error: io::Error) -> TransactionError {
+        TransactionError {
+            error,
+            rollback_failures: self.rollback(),
+        }
+    }
+
+    fn rollback(&mut self) -> Vec<(RouteOp, io::Error)> {
+        let mut failures = vec![];
+        while let Some(op) = self.undo.pop() {
+            let ret = match &op {
+                RouteOp::Add(route) => self.sock.add(route),
+                RouteOp::Replace(route) => self.sock.replace(route),
+                RouteOp::Delete(route) => self.sock.delete(route),
+            };
+            if let Err(e) = ret {
+                failures.push((op, e));
+            }
+        }
+
+        failures
+    }
+
+    /// The installed route `selects` picks for `route`
+    fn find(
+        &mut self,
+        route: &Route,
+        selects: fn(&Route, &Route) -> bool,
+    ) -> io::Result<Option<Route>> {
+        let filter = RouteFilter::new()
+            .family(IpFamily::of(&route.destination))
+            .within(route.destination, route.prefix);
+
+        Ok(self
+            .sock
+            .list(&filter)?
+            .into_iter()
+            .find(|installed| selects(route, installed)))
+    }
+}
+
+impl<T: RouteAction> Drop for RouteTransaction<'_, T> {
+    fn drop(&mut self) {
+        self.rollback();
+    }
+}
+
+/// A replace of `route` swaps out `installed`: same destination, prefix,
+/// table, tos and, if set, metric
+fn replaced_by(route: &Route, installed: &Route) -> bool {
+    // routes read back always carry their table, `None` is the main one
+    let main = Some(RT_TABLE_MAIN);
+
+    installed.destination == route.destination
+        && installed.prefix == route.prefix
+        && installed.table.or(main) == route.table.or(main)
+        && installed.tos == route.tos
+        && (route.metric.is_none() || installed.metric == route.metric)
+}
+
+/// A delete of `route` removes `installed`: as for a replace, and
+/// whichever of gateway, interface, kind, protocol, scope and next hops
+/// are set
+fn deleted_by(route: &Route, installed: &Route) -> bool {
+    replaced_by(route, installed)
+        && (route.gateway.is_none() || installed.gateway == route.gateway)
+        && (route.ifindex.is_none() || installed.ifindex == route.ifindex)
+        // like the kernel, unicast and no scope match any
+        && (route.kind == RouteKind::Unicast || installed.kind == route.kind)
+        && (route.protocol.is_none() || installed.protocol == route.protocol)
+        && (matches!(route.scope, None | Some(RouteScope::NoWhere))
+            || installed.scope == route.scope)
+        && next_hops_match(&installed.next_hops, &route.next_hops)
+}
+
+/// Next hops `wanted` select the installed `next_hops` pairwise, each by
+/// gateway and interface when set, none select any
+fn next_hops_match(next_hops: &[NextHop], wanted: &[NextHop]) -> bool {
+    wanted.is_empty()
+        || (next_hops.len() == wanted.len()
+            && next_hops.iter().zip(wanted).all(|(nh, want)| {
+                (want.gateway.is_none() || nh.gateway == want.gateway)
+                    && (want.ifindex.is_none() || nh.ifindex == want.ifindex)
+            }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FlushReport, RouteChange};
+
+    /// In-memory routes, logging every change and failing those in `fail`
+    #[derive(Default)]
+    struct MockSock {
+        routes: Vec<Route>,
+        log: Vec<String>,
+        fail: Vec<String>,
+    }
+
+    impl MockSock {
+        fn apply(&mut self, op: &str, route: &Route) -> io::Result<()> {
+            let entry = format!("{op} {}/{}", route.destination, route.prefix);
+            self.log.push(entry.clone());
+            if self.fail.contains(&entry) {
+                return Err(io::ErrorKind::PermissionDenied.into());
+            }
+
+            match op {
+                "add" => self.routes.push(route.clone()),
+                "replace" => {
+                    self.routes.retain(|r| !replaced_by(route, r));
+                    self.routes.push(route.clone());
+                }
+                _ => {
+                    let i = self.routes.iter().position(|r| deleted_by(route, r));
+                    self.routes.remove(i.ok_or(io::ErrorKind::NotFound)?);
+                }
+            }
+
+            Ok(())
+        }
+    }
+
+    impl RouteAction for MockSock {
+        fn add(&mut self, route: &Route) -> io::Result<()> {
+            self.apply("add", route)
+        }
+
+        fn replace(&mut self, route: &Route) -> io::Result<()> {
+            self.apply("replace", route)
+        }
+
+        fn change(&mut self, _route: &Route) -> io::Result<()> {
+            unimplemented!()
+        }
+
+        fn append(&mut self, _route: &Route) -> io::Result<()> {
+            unimplemented!()
+        }
+
+        fn delete(&mut self, route: &Route) -> io::Result<()> {
+            self.apply("delete", route)
+        }
+
+        fn get(&mut self, _route: &Route) -> io::Result<Route> {
+            unimplemented!()
+        }
+
+        fn list(&mut self, filter: &RouteFilter) -> io::Result<Vec<Route>> {
+            Ok(self
+                .routes
+                .iter()
+                .filter(|r| filter.matches(r))
+                .cloned()
+                .collect())
+        }
+
+        fn monitor(&mut self, _buf: &mut [u8]) -> io::Result<(RouteChange, Route)> {
+            unimplemented!()
+        }
+
+        fn flush(&mut self, _filter: &RouteFilter) -> io::Result<FlushReport> {
+            unimplemented!()
+        }
+    }
+
+    fn route(destination: &str, prefix: u8) -> Route {
+        Route::new(destination.parse().unwrap(), prefix)
+    }
+
+    fn via(destination: &str, prefix: u8, gateway: &str) -> Route {
+        route(destination, prefix).gateway(gateway.parse().unwrap())
+    }
+
+    /// Installed routes as `destination/prefix gateway metric`
+    fn installed(sock: &MockSock) -> Vec<String> {
+        sock.routes
+            .iter()
+            .map(|r| {
+                format!(
+                    "{}/{} {:?} {:?}",
+                    r.destination, r.prefix, r.gateway, r.metric
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn failed_step_rolls_back_in_reverse_order() {
+        let mut sock = MockSock {
+            fail: vec!["add 10.3.0.0/16".into()],
+            ..Default::default()
+        };
+
+        let mut tx = RouteTransaction::new(&mut sock);
+        tx.add(&route("10.1.0.0", 16)).unwrap();
+        tx.add(&route("10.2.0.0", 16)).unwrap();
+        let err = tx.add(&route("10.3.0.0", 16)).unwrap_err();
+        assert_eq!(err.error.kind(), io::ErrorKind::PermissionDenied);
+        assert!(err.rollback_failures.is_empty());
+        drop(tx);
+
+        assert_eq!(
+            sock.log,
+            [
+                "add 10.1.0.0/16",
+                "add 10.2.0.0/16",
+                "add 10.3.0.0/16",
+                "delete 10.2.0.0/16",
+                "delete 10.1.0.0/16",
+            ]
+        );
+        assert!(sock.routes.is_empty());
+    }
+
+    #[test]
+    fn abort_restores_replaced_and_deleted_routes() {
+        let mut sock = MockSock {
+            routes: vec![via("10.1.0.0", 16, "192.0.2.1"), route("10.2.0.0", 16)],
+            ..Default::default()
+        };
+
+        let mut tx = RouteTransaction::new(&mut sock);
+        tx.replace(&via("10.1.0.0", 16, "192.0.2.2")).unwrap();
+        tx.delete(&route("10.2.0.0", 16)).unwrap();
+        assert!(tx.abort().is_empty());
+
+        assert_eq!(
+            sock.log,
+            [
+                "replace 10.1.0.0/16",
+                "delete 10.2.0.0/16",
+                "add 10.2.0.0/16",
+                "replace 10.1.0.0/16",
+            ]
+        );
+        assert_eq!(
+            installed(&sock),
+            ["10.2.0.0/16 None None", "10.1.0.0/16 Some(192.0.2.1) None"]
+        );
+    }
+
+    #[test]
+    fn drop_rolls_back_and_commit_keeps() {
+        let mut sock = MockSock::default();
+
+        let mut tx = RouteTransaction::new(&mut sock);
+        tx.add(&route("10.1.0.0", 16)).unwrap();
+        drop(tx);
+        assert!(sock.routes.is_empty());
+
+        let mut tx = RouteTransaction::new(&mut sock);
+        tx.add(&route("10.1.0.0", 16)).unwrap();
+        tx.commit();
+        assert_eq!(installed(&sock), ["10.1.0.0/16 None None"]);
+        assert_eq!(
+            sock.log,
+            ["add 10.1.0.0/16", "delete 10.1.0.0/16", "add 10.1.0.0/16"]
+        );
+    }
+
+    #[test]
+    fn rollback_failures_are_reported() {
+        let mut sock = MockSock {
+            fail: vec!["add 10.3.0.0/16".into(), "delete 10.2.0.0/16".into()],
+            ..Default::default()
+        };
+
+        let mut tx = RouteTransaction::new(&mut sock);
+        tx.add(&route("10.1.0.0", 16)).unwrap();
+        tx.add(&route("10.2.0.0", 16)).unwrap();
+        let err = tx.add(&route("10.3.0.0", 16)).unwrap_err();
+
+        assert_eq!(err.rollback_failures.len(), 1);
+        let (op, error) = &err.rollback_failures[0];
+        assert!(matches!(op, RouteOp::Delete(r) if r.destination.to_string() == "10.2.0.0"));
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(
+            err.to_string(),
+            "permission denied, 1 change(s) could not be rolled back"
+        );
+        drop(tx);
+        assert_eq!(installed(&sock), ["10.2.0.0/16 None None"]);
+    }
+
+    #[test]
+    fn delete_restores_the_route_it_removed() {
+        let mut sock = MockSock {
+            routes: vec![
+                via("10.0.0.0", 8, "192.0.2.1").metric(100),
+                via("10.0.0.0", 8, "192.0.2.2").metric(200),
+            ],
+            ..Default::default()
+        };
+
+        let mut tx = RouteTransaction::new(&mut sock);
+        tx.delete(&via("10.0.0.0", 8, "192.0.2.2")).unwrap();
+        assert_eq!(installed(tx.sock), ["10.0.0.0/8 Some(192.0.2.1) Some(100)"]);
+        assert!(tx.abort().is_empty());
+
+        assert_eq!(
+            installed(&sock),
+            [
+                "10.0.0.0/8 Some(192.0.2.1) Some(100)",
+                "10.0.0.0/8 Some(192.0.2.2) Some(200)",
+            ]
+        );
+    }
+}